@@ -0,0 +1,116 @@
+/*
+Automatic gain control of the input
+
+Samples through the receive filter chain (DC offset removed, see `front_end`)
+-> level: power of the samples, rising fast and falling slowly, i.e. about the peak power
+-> gain: the target level over the level, up to `MAX_GAIN`
+-> the samples, delayed by `LOOKAHEAD`, times the gain
+-> preamble detection and demodulation
+
+The level runs `LOOKAHEAD` samples ahead of the output, the gain has already fallen when a loud
+preamble after a silence comes out, so that the preamble is not distorted. The gain is held while a
+frame is received: the amplitude of the symbols keeps following the preamble (QAM).
+*/
+use std::collections::VecDeque;
+
+// peak amplitude of the output
+pub const TARGET_LEVEL: f32 = 0.5;
+// the gain does not exceed 60dB, e.g. in a silence
+pub const MAX_GAIN: f32 = 1000.0;
+// an input sample at this level is taken as clipped by the sound card
+pub const CLIP_LEVEL: f32 = 0.99;
+// samples of the delay of the output after the level, 10ms at 48kHz
+pub const LOOKAHEAD: usize = 480;
+// time constants of the level when it rises and when it falls, in seconds
+const ATTACK_TIME: f32 = 0.001;
+const RELEASE_TIME: f32 = 0.5;
+
+// the level of the input during a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameLevel {
+    // gain of the AGC applied to the frame
+    pub gain: f32,
+    // peak of the raw input since the previous frame, 1 is the full scale of the sound card
+    pub input_peak: f32,
+}
+
+impl FrameLevel {
+    pub fn is_clipping(&self) -> bool {
+        self.input_peak >= CLIP_LEVEL
+    }
+
+    // the AGC can not lift the signal to the target level
+    pub fn is_too_quiet(&self) -> bool {
+        self.gain >= MAX_GAIN
+    }
+}
+
+pub struct Agc {
+    // smoothing coefficients of the level, per sample
+    attack: f32,
+    release: f32,
+    // power of the samples in the lookahead
+    level: f32,
+    gain: f32,
+    hold: bool,
+    delay_line: VecDeque<f32>,
+    input_peak: f32,
+}
+
+impl Agc {
+    pub fn new(sample_rate: u32) -> Self {
+        let coefficient = |time: f32| 1.0 - (-1.0 / (time * sample_rate as f32)).exp();
+        Agc {
+            attack: coefficient(ATTACK_TIME),
+            release: coefficient(RELEASE_TIME),
+            // the level rises to the first samples
+            level: 0.0,
+            gain: MAX_GAIN,
+            hold: false,
+            delay_line: VecDeque::from(vec![0.0; LOOKAHEAD]),
+            input_peak: 0.0,
+        }
+    }
+
+    // track the peak of the raw input of the sound card, before the receive filter chain
+    pub fn observe_input(&mut self, sample: f32) {
+        self.input_peak = self.input_peak.max(sample.abs());
+    }
+
+    // @param sample: the input through the receive filter chain
+    // @return: the sample `LOOKAHEAD` samples before, times the gain
+    pub fn run(&mut self, sample: f32) -> f32 {
+        self.delay_line.push_back(sample);
+        let output = self.delay_line.pop_front().unwrap_or(0.0);
+        if !self.hold {
+            let power = sample * sample;
+            let coefficient = if power > self.level {
+                self.attack
+            } else {
+                self.release
+            };
+            self.level += coefficient * (power - self.level);
+            self.gain = (TARGET_LEVEL / self.level.sqrt()).min(MAX_GAIN);
+        }
+        output * self.gain
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    // hold the gain from the detection of a preamble to the end of the frame
+    pub fn hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    // the level of the frame, the peak of the input starts again for the next frame
+    pub fn take_level(&mut self) -> FrameLevel {
+        let level = FrameLevel {
+            gain: self.gain,
+            input_peak: self.input_peak,
+        };
+        self.input_peak = 0.0;
+        level
+    }
+}
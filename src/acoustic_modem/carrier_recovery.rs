@@ -0,0 +1,89 @@
+/*
+Carrier recovery of the coherent PSK / QAM carriers
+
+(I, Q) of a received symbol
+-> rotated back by the tracked phase
+-> nearest constellation point (decision)
+-> phase error between the symbol and the decision
+-> second order loop filter: phase and frequency offset
+
+The preamble only aligns the phase at the beginning of a frame, the clock mismatch between the
+sound cards of the sender and the receiver makes the phase drift during the frame.
+*/
+use super::constellation::Constellation;
+
+// normalized loop bandwidth (to the symbol rate) and damping factor of the loop
+const LOOP_BANDWIDTH: f32 = 0.02;
+const LOOP_DAMPING: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// state of the loop of one carrier, for diagnostics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CarrierTracking {
+    // phase offset in rad, applied to the next symbol
+    pub phase: f32,
+    // frequency offset in Hz
+    pub freq_offset: f32,
+    // phase error of the last symbol in rad
+    pub phase_error: f32,
+}
+
+// Decision-directed Costas loop: a PLL driven by the phase error against the decisions.
+#[derive(Clone, Debug)]
+pub struct CostasLoop {
+    // symbols per second, to convert the frequency offset into Hz
+    symbol_rate: f32,
+    alpha: f32,
+    beta: f32,
+    // phase and frequency offset in rad, frequency in rad per symbol
+    phase: f32,
+    freq: f32,
+    phase_error: f32,
+}
+
+impl CostasLoop {
+    pub fn new(symbol_rate: f32) -> Self {
+        // proportional and integral gains of the loop filter
+        let theta = LOOP_BANDWIDTH / (LOOP_DAMPING + 1.0 / (4.0 * LOOP_DAMPING));
+        let denominator = 1.0 + 2.0 * LOOP_DAMPING * theta + theta * theta;
+        CostasLoop {
+            symbol_rate,
+            alpha: 4.0 * LOOP_DAMPING * theta / denominator,
+            beta: 4.0 * theta * theta / denominator,
+            phase: 0.0,
+            freq: 0.0,
+            phase_error: 0.0,
+        }
+    }
+
+    // called when a new frame begins, the preamble aligns the phase again
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.freq = 0.0;
+        self.phase_error = 0.0;
+    }
+
+    // rotate the symbol back by the tracked phase, and update the loop by the decision of it
+    pub fn track(&mut self, constellation: Constellation, i: f32, q: f32) -> (f32, f32) {
+        let (sin, cos) = self.phase.sin_cos();
+        let (i, q) = (i * cos + q * sin, q * cos - i * sin);
+
+        let (decision_i, decision_q) = constellation.map(&constellation.demap(i, q));
+        // arg((i + jq) * conj(decision))
+        let error = (q * decision_i - i * decision_q).atan2(i * decision_i + q * decision_q);
+        self.phase_error = error;
+        self.freq += self.beta * error;
+        self.phase += self.alpha * error + self.freq;
+        self.phase = (self.phase + std::f32::consts::PI).rem_euclid(2.0 * std::f32::consts::PI)
+            - std::f32::consts::PI;
+
+        (i, q)
+    }
+
+    pub fn state(&self) -> CarrierTracking {
+        CarrierTracking {
+            phase: self.phase,
+            freq_offset: self.freq * self.symbol_rate / (2.0 * std::f32::consts::PI),
+            phase_error: self.phase_error,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+/*
+Modulated Signal
+-> Channel Model (attenuation, echo, frequency offset, clock drift, delay, noise)
+-> Received Signal
+*/
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+// length of the FIR Hilbert transformer used by the frequency offset, must be odd
+const HILBERT_TAPS: usize = 127;
+
+// All the impairments are disabled by default, i.e. the default channel is an ideal loopback.
+#[derive(Clone, Debug)]
+pub struct ChannelConfig {
+    // amplitude gain applied to the whole signal
+    pub attenuation: f32,
+    // echo taps: (delay in samples, gain), added to the direct path
+    pub echo_taps: Vec<(usize, f32)>,
+    // frequency offset in Hz, applied to every frequency component of the signal
+    pub freq_offset: f32,
+    // sample clock skew of the sender against the receiver, in ppm
+    pub clock_skew_ppm: f64,
+    // the leading silence is chosen uniformly in [min, max] samples
+    pub leading_silence: (usize, usize),
+    pub trailing_silence: usize,
+    // target SNR in dB of the received signal, None for no noise
+    pub snr_db: Option<f32>,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig {
+            attenuation: 1.0,
+            echo_taps: vec![],
+            freq_offset: 0.0,
+            clock_skew_ppm: 0.0,
+            leading_silence: (0, 0),
+            trailing_silence: 0,
+            snr_db: None,
+        }
+    }
+}
+
+pub struct ChannelModel {
+    config: ChannelConfig,
+    sample_rate: u32,
+    rng: StdRng,
+}
+
+impl ChannelModel {
+    // the same seed and config always produce the same output
+    pub fn new(config: ChannelConfig, sample_rate: u32, seed: u64) -> Self {
+        ChannelModel {
+            config,
+            sample_rate,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn config(&self) -> &ChannelConfig {
+        &self.config
+    }
+
+    // pass the signal through the channel
+    pub fn transmit(&mut self, signal: &[f32]) -> Vec<f32> {
+        let mut output: Vec<f32> = signal
+            .iter()
+            .map(|&x| x * self.config.attenuation)
+            .collect();
+
+        if !self.config.echo_taps.is_empty() {
+            output = apply_echo(&output, &self.config.echo_taps);
+        }
+
+        if self.config.freq_offset != 0.0 {
+            output = shift_frequency(&output, self.config.freq_offset, self.sample_rate);
+        }
+
+        if self.config.clock_skew_ppm != 0.0 {
+            output = resample(&output, 1.0 + self.config.clock_skew_ppm * 1e-6);
+        }
+
+        // the power of the signal is measured before the silence is inserted
+        let signal_power = average_power(&output);
+
+        let (min_silence, max_silence) = self.config.leading_silence;
+        let leading_silence = if max_silence > min_silence {
+            self.rng.gen_range(min_silence..=max_silence)
+        } else {
+            min_silence
+        };
+        let mut received = vec![0.0; leading_silence];
+        received.extend(output);
+        received.extend(vec![0.0; self.config.trailing_silence]);
+
+        if let Some(snr_db) = self.config.snr_db {
+            let noise_power = signal_power / 10.0_f32.powf(snr_db / 10.0);
+            let normal = Normal::new(0.0, noise_power.sqrt()).unwrap();
+            for sample in received.iter_mut() {
+                *sample += normal.sample(&mut self.rng);
+            }
+        }
+
+        return received;
+    }
+}
+
+pub fn average_power(signal: &[f32]) -> f32 {
+    if signal.is_empty() {
+        return 0.0;
+    }
+    signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32
+}
+
+fn apply_echo(signal: &[f32], echo_taps: &Vec<(usize, f32)>) -> Vec<f32> {
+    let max_delay = echo_taps.iter().map(|&(delay, _)| delay).max().unwrap_or(0);
+    let mut output = signal.to_vec();
+    output.extend(vec![0.0; max_delay]);
+    for &(delay, gain) in echo_taps {
+        for (i, &x) in signal.iter().enumerate() {
+            output[i + delay] += x * gain;
+        }
+    }
+    output
+}
+
+// single sideband shift: x(t) cos(2 pi f t) - H{x}(t) sin(2 pi f t)
+fn shift_frequency(signal: &[f32], freq_offset: f32, sample_rate: u32) -> Vec<f32> {
+    let half = HILBERT_TAPS / 2;
+    let hilbert: Vec<f32> = (0..HILBERT_TAPS)
+        .map(|i| {
+            let n = i as isize - half as isize;
+            if n % 2 == 0 {
+                0.0
+            } else {
+                // Hamming windowed ideal Hilbert transformer
+                let window = 0.54
+                    - 0.46
+                        * (2.0 * std::f32::consts::PI * i as f32 / (HILBERT_TAPS - 1) as f32)
+                            .cos();
+                2.0 / (std::f32::consts::PI * n as f32) * window
+            }
+        })
+        .collect();
+
+    (0..signal.len())
+        .map(|i| {
+            let mut quadrature = 0.0;
+            for (k, &h) in hilbert.iter().enumerate() {
+                // align the FIR output with the input: the filter delays by `half` samples
+                let index = i as isize + half as isize - k as isize;
+                if index >= 0 && (index as usize) < signal.len() {
+                    quadrature += h * signal[index as usize];
+                }
+            }
+            let phase = 2.0 * std::f64::consts::PI * freq_offset as f64 * i as f64
+                / sample_rate as f64;
+            (signal[i] as f64 * phase.cos() - quadrature as f64 * phase.sin()) as f32
+        })
+        .collect()
+}
+
+// read the signal at `ratio` times the original rate, with linear interpolation
+// ratio > 1.0: the sender clock is faster than the receiver clock, the received signal is shorter
+pub fn resample(signal: &[f32], ratio: f64) -> Vec<f32> {
+    if signal.len() < 2 {
+        return signal.to_vec();
+    }
+    let mut output = vec![];
+    let mut t = 0.0;
+    while t < (signal.len() - 1) as f64 {
+        let index = t.floor() as usize;
+        let frac = (t - index as f64) as f32;
+        output.push(signal[index] * (1.0 - frac) + signal[index + 1] * frac);
+        t += ratio;
+    }
+    output
+}
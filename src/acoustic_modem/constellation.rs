@@ -0,0 +1,262 @@
+/*
+Bits
+-> Constellation point (I: sin carrier, Q: cos carrier)
+-> Bits (nearest point)
+
+All the constellations are Gray-coded, and normalized to a peak amplitude of 1, so that
+the modulated signal never exceeds [-1, 1].
+
+Differential constellations (DBPSK, DQPSK) carry the bits in the phase change between two
+symbols, a reference symbol is sent at the beginning of each frame.
+*/
+use crate::utils::Bit;
+
+// amplitude of the corner points (+-3, +-3) of 16-QAM
+const QAM16_PEAK: f32 = 3.0 * std::f32::consts::SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Constellation {
+    Bpsk,
+    Qpsk,
+    Psk8,
+    Qam16,
+    Dbpsk,
+    Dqpsk,
+}
+
+impl Constellation {
+    pub fn bits_per_symbol(&self) -> usize {
+        match self {
+            Constellation::Bpsk => 1,
+            Constellation::Qpsk => 2,
+            Constellation::Psk8 => 3,
+            Constellation::Qam16 => 4,
+            Constellation::Dbpsk => 1,
+            Constellation::Dqpsk => 2,
+        }
+    }
+
+    pub fn is_differential(&self) -> bool {
+        self.base() != *self
+    }
+
+    // the constellation of the phase changes of a differential constellation
+    fn base(&self) -> Constellation {
+        match self {
+            Constellation::Dbpsk => Constellation::Bpsk,
+            Constellation::Dqpsk => Constellation::Qpsk,
+            _ => *self,
+        }
+    }
+
+    // whether the amplitude carries information, i.e. the receiver needs the channel gain
+    pub fn need_amplitude(&self) -> bool {
+        *self == Constellation::Qam16
+    }
+
+    // map `bits_per_symbol` bits into (I, Q), the phase change for differential constellations
+    pub fn map(&self, bits: &[Bit]) -> (f32, f32) {
+        assert_eq!(bits.len(), self.bits_per_symbol());
+        match self {
+            Constellation::Dbpsk | Constellation::Dqpsk => self.base().map(bits),
+            // bit 0 -> +sin, bit 1 -> -sin
+            Constellation::Bpsk => (1.0 - 2.0 * bits[0] as f32, 0.0),
+            Constellation::Qpsk => (
+                (1.0 - 2.0 * bits[0] as f32) / 2.0_f32.sqrt(),
+                (1.0 - 2.0 * bits[1] as f32) / 2.0_f32.sqrt(),
+            ),
+            Constellation::Psk8 => {
+                let gray = (bits[0] << 2 | bits[1] << 1 | bits[2]) as usize;
+                let phase = gray_decode(gray) as f32 * std::f32::consts::PI / 4.0;
+                (phase.cos(), phase.sin())
+            }
+            Constellation::Qam16 => (
+                qam16_level(bits[0], bits[1]) / QAM16_PEAK,
+                qam16_level(bits[2], bits[3]) / QAM16_PEAK,
+            ),
+        }
+    }
+
+    // the bits of the nearest constellation point, see `DifferentialDetector` for differential constellations
+    pub fn demap(&self, i: f32, q: f32) -> Vec<Bit> {
+        if self.is_differential() {
+            return self.base().demap(i, q);
+        }
+        let mut best = vec![];
+        let mut best_distance = f32::MAX;
+        for bits in self.all_bits() {
+            let (point_i, point_q) = self.map(&bits);
+            let distance = (i - point_i).powi(2) + (q - point_q).powi(2);
+            if distance < best_distance {
+                best_distance = distance;
+                best = bits;
+            }
+        }
+        best
+    }
+
+    // the nearest constellation point, the nearest phase change for differential constellations
+    pub fn decide(&self, i: f32, q: f32) -> (f32, f32) {
+        self.base().map(&self.demap(i, q))
+    }
+
+    // max-log LLR of each bit of the received point, ln(P(bit = 0) / P(bit = 1)), i.e. positive
+    // for 0, the phase change for differential constellations
+    // @param noise_var: the power of the noise on the point, I and Q together
+    pub fn llrs(&self, i: f32, q: f32, noise_var: f32) -> Vec<f32> {
+        let base = self.base();
+        // the nearest point with the bit k at 0 and at 1
+        let mut nearest = vec![[f32::MAX; 2]; base.bits_per_symbol()];
+        for bits in base.all_bits() {
+            let (point_i, point_q) = base.map(&bits);
+            let distance = (i - point_i).powi(2) + (q - point_q).powi(2);
+            for (k, &bit) in bits.iter().enumerate() {
+                nearest[k][bit as usize] = nearest[k][bit as usize].min(distance);
+            }
+        }
+        nearest
+            .iter()
+            .map(|distance| (distance[1] - distance[0]) / noise_var)
+            .collect()
+    }
+
+    // half the distance between the two nearest points, i.e. from a point to the decision boundary
+    pub fn half_distance(&self) -> f32 {
+        let points: Vec<(f32, f32)> = self
+            .base()
+            .all_bits()
+            .iter()
+            .map(|bits| self.base().map(bits))
+            .collect();
+        let mut min_distance = f32::MAX;
+        for (n, a) in points.iter().enumerate() {
+            for b in &points[n + 1..] {
+                min_distance = min_distance.min((a.0 - b.0).hypot(a.1 - b.1));
+            }
+        }
+        min_distance / 2.0
+    }
+
+    // signed distance from the received point to the boundary of the decision region of `decided`,
+    // i.e. to the nearest bisector with another point: `half_distance` on the point, 0 on the
+    // boundary, negative if another point is nearer. The outer points have no boundary outwards.
+    pub fn boundary_distance(&self, received: (f32, f32), decided: (f32, f32)) -> f32 {
+        let base = self.base();
+        base.all_bits()
+            .iter()
+            .map(|bits| base.map(bits))
+            .filter_map(|point| {
+                let distance = (point.0 - decided.0).hypot(point.1 - decided.1);
+                if distance < 1e-6 {
+                    return None;
+                }
+                // the received point projected on the line from `decided` to `point`
+                let along = ((received.0 - decided.0) * (point.0 - decided.0)
+                    + (received.1 - decided.1) * (point.1 - decided.1))
+                    / distance;
+                Some(distance / 2.0 - along)
+            })
+            .fold(f32::MAX, f32::min)
+    }
+
+    // map a bit stream, the last symbol is padded with 0
+    // A differential stream begins with the reference symbol (1, 0), one symbol longer than the others.
+    pub fn map_bits(&self, bits: &Vec<Bit>) -> Vec<(f32, f32)> {
+        let bits_per_symbol = self.bits_per_symbol();
+        let points = bits.chunks(bits_per_symbol).map(|chunk| {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(bits_per_symbol, 0);
+            self.map(&chunk)
+        });
+        if !self.is_differential() {
+            return points.collect();
+        }
+
+        let mut symbols = vec![(1.0, 0.0)];
+        for (change_i, change_q) in points {
+            let (prev_i, prev_q) = *symbols.last().unwrap();
+            // rotate the previous symbol by the phase change
+            symbols.push((
+                prev_i * change_i - prev_q * change_q,
+                prev_i * change_q + prev_q * change_i,
+            ));
+        }
+        symbols
+    }
+
+    fn all_bits(&self) -> Vec<Vec<Bit>> {
+        let bits_per_symbol = self.bits_per_symbol();
+        (0..1usize << bits_per_symbol)
+            .map(|value| {
+                (0..bits_per_symbol)
+                    .map(|j| ((value >> (bits_per_symbol - 1 - j)) & 1) as Bit)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+// Differential detection of one carrier: the phase change from the previous symbol is demapped.
+#[derive(Clone, Debug, Default)]
+pub struct DifferentialDetector {
+    prev: Option<(f32, f32)>,
+    // the phase change of the last symbol on the unit circle, None for the reference symbol
+    change: Option<(f32, f32)>,
+}
+
+impl DifferentialDetector {
+    // called when a new frame begins, the next symbol is the reference
+    pub fn reset(&mut self) {
+        self.prev = None;
+        self.change = None;
+    }
+
+    pub fn change(&self) -> Option<(f32, f32)> {
+        self.change
+    }
+
+    // return no bits for the reference symbol
+    pub fn detect(&mut self, constellation: Constellation, i: f32, q: f32) -> Vec<Bit> {
+        let prev = self.prev.replace((i, q));
+        let (prev_i, prev_q) = match prev {
+            Some(prev) => prev,
+            None => return vec![],
+        };
+
+        // (i + jq) * conj(prev_i + j prev_q), normalized to the unit circle
+        let change_i = i * prev_i + q * prev_q;
+        let change_q = q * prev_i - i * prev_q;
+        let norm = (change_i * change_i + change_q * change_q).sqrt();
+        let change = if norm > 0.0 {
+            (change_i / norm, change_q / norm)
+        } else {
+            (change_i, change_q)
+        };
+        self.change = Some(change);
+        constellation.demap(change.0, change.1)
+    }
+}
+
+// Gray code 00, 01, 11, 10 -> -3, -1, +1, +3
+fn qam16_level(bit_0: Bit, bit_1: Bit) -> f32 {
+    match (bit_0, bit_1) {
+        (0, 0) => -3.0,
+        (0, 1) => -1.0,
+        (1, 1) => 1.0,
+        _ => 3.0,
+    }
+}
+
+pub fn gray_encode(value: usize) -> usize {
+    value ^ (value >> 1)
+}
+
+pub fn gray_decode(gray: usize) -> usize {
+    let mut value = gray;
+    let mut shift = gray >> 1;
+    while shift != 0 {
+        value ^= shift;
+        shift >>= 1;
+    }
+    value
+}
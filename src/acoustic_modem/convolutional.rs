@@ -0,0 +1,136 @@
+/*
+Convolutional code, the inner code of a frame
+
+Bits of the Reed-Solomon codewords (`frame_codec::FrameCodec`)
+-> encoder of constraint length 7, generators 133 and 171 (octal), 6 tail bits at 0 so that the
+   trellis ends in the state 0
+-> rate 1/2, or rate 2/3 by puncturing every 4th coded bit
+-> modulation
+
+LLRs of the received bits (0 for the punctured ones)
+-> Viterbi decoder: the path of the trellis with the largest correlation to the LLRs
+-> bits of the Reed-Solomon codewords
+
+The noise gives isolated wrong bits, each of them spoils a whole hexbit of RS. The Viterbi decoder
+corrects the isolated ones, and its rare errors come in bursts, which RS corrects in few hexbits.
+*/
+use crate::utils::Bit;
+
+pub const CONSTRAINT_LENGTH: usize = 7;
+// generator polynomials, the newest bit is the most significant one
+const GENERATORS: [usize; 2] = [0o133, 0o171];
+// coded bits sent out of each group of 4 for rate 2/3
+const PUNCTURE_2_3: [bool; 4] = [true, true, true, false];
+
+const STATES: usize = 1 << (CONSTRAINT_LENGTH - 1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConvCode {
+    Rate1_2,
+    // rate 1/2 punctured
+    Rate2_3,
+}
+
+impl ConvCode {
+    // the coded bits of `len` bits, with the tail
+    pub fn encoded_len(&self, len: usize) -> usize {
+        let coded = 2 * (len + CONSTRAINT_LENGTH - 1);
+        match self {
+            ConvCode::Rate1_2 => coded,
+            ConvCode::Rate2_3 => (0..coded).filter(|&k| self.is_sent(k)).count(),
+        }
+    }
+
+    // whether the k-th coded bit of the mother code is sent
+    fn is_sent(&self, k: usize) -> bool {
+        match self {
+            ConvCode::Rate1_2 => true,
+            ConvCode::Rate2_3 => PUNCTURE_2_3[k % PUNCTURE_2_3.len()],
+        }
+    }
+
+    pub fn encode(&self, bits: &[Bit]) -> Vec<Bit> {
+        let mut state = 0;
+        let mut coded = vec![];
+        let tail = [0; CONSTRAINT_LENGTH - 1];
+        for &bit in bits.iter().chain(tail.iter()) {
+            let (outputs, next) = transition(state, bit);
+            coded.extend(outputs);
+            state = next;
+        }
+        coded
+            .into_iter()
+            .enumerate()
+            .filter(|&(k, _)| self.is_sent(k))
+            .map(|(_, bit)| bit)
+            .collect()
+    }
+
+    // the decoded bits of the LLRs ln(P(0) / P(1)) of the coded bits, without the tail
+    pub fn decode(&self, llrs: &[f32]) -> Vec<Bit> {
+        // the punctured bits are unknown
+        let mut received = vec![];
+        let mut llrs = llrs.iter();
+        let mut k = 0;
+        while received.len() % 2 == 1 || llrs.len() > 0 {
+            if self.is_sent(k) {
+                match llrs.next() {
+                    Some(&llr) => received.push(llr),
+                    None => break,
+                }
+            } else {
+                received.push(0.0);
+            }
+            k += 1;
+        }
+        received.truncate(received.len() / 2 * 2);
+        let steps = received.len() / 2;
+
+        // path metrics, the trellis starts in the state 0
+        let mut metrics = vec![f32::NEG_INFINITY; STATES];
+        metrics[0] = 0.0;
+        // the previous state and the bit of the survivor of each state at each step
+        let mut survivors = vec![[(0u8, 0 as Bit); STATES]; steps];
+        for (step, pair) in received.chunks_exact(2).enumerate() {
+            let mut next_metrics = vec![f32::NEG_INFINITY; STATES];
+            for (state, &path_metric) in metrics.iter().enumerate() {
+                if path_metric == f32::NEG_INFINITY {
+                    continue;
+                }
+                for bit in [0, 1] {
+                    let (outputs, next) = transition(state, bit);
+                    // bit 0 agrees with a positive LLR
+                    let branch: f32 = outputs
+                        .iter()
+                        .zip(pair)
+                        .map(|(&output, &llr)| if output == 0 { llr } else { -llr })
+                        .sum();
+                    let metric = path_metric + branch;
+                    if metric > next_metrics[next] {
+                        next_metrics[next] = metric;
+                        survivors[step][next] = (state as u8, bit);
+                    }
+                }
+            }
+            metrics = next_metrics;
+        }
+
+        // trace back from the state 0 of the tail
+        let mut state = 0;
+        let mut bits = vec![0; steps];
+        for step in (0..steps).rev() {
+            let (previous, bit) = survivors[step][state];
+            bits[step] = bit;
+            state = previous as usize;
+        }
+        bits.truncate(steps.saturating_sub(CONSTRAINT_LENGTH - 1));
+        bits
+    }
+}
+
+// the coded bits and the next state of the input bit in the state (the last 6 bits, newest first)
+fn transition(state: usize, bit: Bit) -> ([Bit; 2], usize) {
+    let register = (bit as usize) << (CONSTRAINT_LENGTH - 1) | state;
+    let outputs = GENERATORS.map(|generator| ((register & generator).count_ones() % 2) as Bit);
+    (outputs, register >> 1)
+}
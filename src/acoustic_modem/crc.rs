@@ -0,0 +1,48 @@
+/*
+Cyclic redundancy checks of the frames
+
+Bits (the length and the data of a frame)
+-> polynomial division, most significant bit first
+-> remainder, appended to the frame
+
+CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF, no final XOR
+CRC-32/BZIP2: polynomial 0x04C11DB7, initial value 0xFFFFFFFF, final XOR 0xFFFFFFFF
+
+Both are computed on the bit stream without reflection, so that a frame does not need to be a whole
+number of bytes.
+*/
+use crate::utils::Bit;
+
+pub fn crc16(bits: &[Bit]) -> u16 {
+    crc(bits, 0x1021, 16, 0xFFFF, 0) as u16
+}
+
+pub fn crc32(bits: &[Bit]) -> u32 {
+    crc(bits, 0x04C1_1DB7, 32, 0xFFFF_FFFF, 0xFFFF_FFFF)
+}
+
+// the `width` bits of `value`, most significant bit first
+pub fn to_bits(value: u32, width: usize) -> Vec<Bit> {
+    (0..width)
+        .rev()
+        .map(|j| ((value >> j) & 1) as Bit)
+        .collect()
+}
+
+fn crc(bits: &[Bit], polynomial: u32, width: usize, init: u32, xor_out: u32) -> u32 {
+    let top = 1u32 << (width - 1);
+    let mask = if width == 32 {
+        u32::MAX
+    } else {
+        (1u32 << width) - 1
+    };
+    let mut register = init;
+    for &bit in bits {
+        let feedback = (register & top != 0) ^ (bit != 0);
+        register = (register << 1) & mask;
+        if feedback {
+            register ^= polynomial;
+        }
+    }
+    register ^ xor_out
+}
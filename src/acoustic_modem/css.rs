@@ -0,0 +1,213 @@
+/*
+Chirp spread spectrum (LoRa-like)
+
+Bits
+-> Gray code -> cyclic shift of the up chirp
+-> [base chirp][shifted chirps]
+-> Output Signal
+
+Received Symbol
+-> de-chirp (multiply by the conjugate of the base chirp) + FFT
+-> peak search -> shift relative to the base chirp -> Bits
+
+The base chirp at the beginning of each frame measures the timing error of the preamble detection,
+both the whole chips and the fraction of a chip.
+*/
+use super::constellation::{gray_decode, gray_encode};
+use super::demodulation::SymbolDemodulator;
+use crate::utils::Bit;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+// the chirps start at the same frequency as the preamble
+pub const CSS_FREQ_LOW: f32 = 800.0;
+// samples of each chip, the bandwidth is sample_rate / CSS_CHIP_LEN (8kHz at 48kHz)
+pub const CSS_CHIP_LEN: usize = 6;
+pub const CSS_DEFAULT_SPREADING_FACTOR: usize = 7;
+
+#[derive(Clone, Debug)]
+pub struct CssConfig {
+    pub sample_rate: u32,
+    // bits of each symbol, a symbol has 2^spreading_factor chips
+    pub spreading_factor: usize,
+}
+
+impl CssConfig {
+    pub fn new(sample_rate: u32, spreading_factor: usize) -> Self {
+        assert!(
+            spreading_factor >= 1 && spreading_factor <= 12,
+            "[CssConfig] spreading factor must be in [1, 12]"
+        );
+        CssConfig {
+            sample_rate,
+            spreading_factor,
+        }
+    }
+
+    pub fn chip_cnt(&self) -> usize {
+        1 << self.spreading_factor
+    }
+
+    pub fn symbol_len(&self) -> usize {
+        self.chip_cnt() * CSS_CHIP_LEN
+    }
+
+    pub fn bandwidth(&self) -> f32 {
+        self.sample_rate as f32 / CSS_CHIP_LEN as f32
+    }
+
+    // phase of each sample of the up chirp cyclically shifted by `shift` chips
+    fn chirp_phase(&self, shift: usize) -> Vec<f64> {
+        let symbol_len = self.symbol_len();
+        let mut phase = 0.0_f64;
+        let mut res = Vec::with_capacity(symbol_len);
+        for n in 0..symbol_len {
+            res.push(phase);
+            let freq = CSS_FREQ_LOW as f64
+                + self.bandwidth() as f64 * ((n + shift * CSS_CHIP_LEN) % symbol_len) as f64
+                    / symbol_len as f64;
+            phase += 2.0 * std::f64::consts::PI * freq / self.sample_rate as f64;
+        }
+        res
+    }
+}
+
+// the last symbol is padded with 0
+pub fn modulate(config: &CssConfig, bits: &Vec<Bit>) -> Vec<f32> {
+    let spreading_factor = config.spreading_factor;
+    // the reference symbol
+    let mut modulated_signal: Vec<f32> = config
+        .chirp_phase(0)
+        .iter()
+        .map(|x| x.sin() as f32)
+        .collect();
+
+    for chunk in bits.chunks(spreading_factor) {
+        let mut value = 0;
+        for j in 0..spreading_factor {
+            value = value << 1 | *chunk.get(j).unwrap_or(&0) as usize;
+        }
+        // Gray code: an error of one chip only flips one bit
+        let shift = gray_encode(value);
+        modulated_signal.extend(config.chirp_phase(shift).iter().map(|x| x.sin() as f32));
+    }
+
+    println!(
+        "[css modulate] spreading factor {}, {} symbols, length {}",
+        spreading_factor,
+        modulated_signal.len() / config.symbol_len(),
+        modulated_signal.len()
+    );
+
+    return modulated_signal;
+}
+
+pub struct CssDemodulator {
+    config: CssConfig,
+    fft: Arc<dyn Fft<f32>>,
+    // conjugate of the analytic base chirp
+    down_chirp: Vec<Complex<f32>>,
+    // shift and fraction of a chip of the reference symbol of the current frame
+    reference: Option<(usize, f32)>,
+}
+
+impl CssDemodulator {
+    pub fn new(config: CssConfig) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(config.symbol_len());
+        let down_chirp = config
+            .chirp_phase(0)
+            .iter()
+            .map(|&phase| Complex::new(phase.cos() as f32, -phase.sin() as f32))
+            .collect();
+        CssDemodulator {
+            config,
+            fft,
+            down_chirp,
+            reference: None,
+        }
+    }
+
+    // the cyclic shift with the largest energy after de-chirping, and the energy of it
+    // @param fraction: a timing error of a fraction of a chip moves the de-chirped tone by the same
+    // fraction of a bin, the tone is moved back by `fraction` bins before the FFT
+    pub fn detect_shift(&self, window: &[f32], fraction: f32) -> (usize, f32) {
+        let symbol_len = self.config.symbol_len();
+        let mut buffer: Vec<Complex<f32>> = window
+            .iter()
+            .zip(self.down_chirp.iter())
+            .enumerate()
+            .map(|(n, (&x, &chirp))| {
+                let phase = -2.0 * std::f32::consts::PI * fraction * n as f32 / symbol_len as f32;
+                chirp * Complex::from_polar(x, phase)
+            })
+            .collect();
+        self.fft.process(&mut buffer);
+
+        // the shifted chirp wraps to the lowest frequency, the tone after the wrap is
+        // `chip_cnt` bins lower than the tone before it
+        let chip_cnt = self.config.chip_cnt();
+        let mut shift = 0;
+        let mut max_energy = -1.0;
+        for k in 0..chip_cnt {
+            let energy =
+                buffer[k].norm_sqr() + buffer[(symbol_len + k - chip_cnt) % symbol_len].norm_sqr();
+            if energy > max_energy {
+                max_energy = energy;
+                shift = k;
+            }
+        }
+        (shift, max_energy)
+    }
+
+    // the shift and the fraction of a chip of the reference symbol
+    // The preamble is detected at an integer sample, so the fraction is a multiple of 1 / CSS_CHIP_LEN.
+    fn detect_reference(&self, window: &[f32]) -> (usize, f32) {
+        let mut best = (0, 0.0);
+        let mut max_energy = -1.0;
+        for i in 0..CSS_CHIP_LEN {
+            let fraction = i as f32 / CSS_CHIP_LEN as f32;
+            let (shift, energy) = self.detect_shift(window, fraction);
+            if energy > max_energy {
+                max_energy = energy;
+                best = (shift, fraction);
+            }
+        }
+        best
+    }
+}
+
+impl SymbolDemodulator for CssDemodulator {
+    fn carrier_cnt(&self) -> usize {
+        1
+    }
+
+    fn symbol_len(&self) -> usize {
+        self.config.symbol_len()
+    }
+
+    // the peak search needs neither the phase nor the gain
+    fn reset(&mut self, _gain: f32) {
+        self.reference = None;
+    }
+
+    // return no bits for the reference symbol
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>> {
+        let (reference_shift, fraction) = match self.reference {
+            Some(reference) => reference,
+            None => {
+                self.reference = Some(self.detect_reference(window));
+                return vec![vec![]];
+            }
+        };
+
+        let chip_cnt = self.config.chip_cnt();
+        let (shift, _) = self.detect_shift(window, fraction);
+        let relative_shift = (shift + chip_cnt - reference_shift) % chip_cnt;
+        let value = gray_decode(relative_shift);
+        let spreading_factor = self.config.spreading_factor;
+        vec![(0..spreading_factor)
+            .map(|j| ((value >> (spreading_factor - 1 - j)) & 1) as Bit)
+            .collect()]
+    }
+}
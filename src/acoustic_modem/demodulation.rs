@@ -1,12 +1,30 @@
-use crate::acoustic_modem::phy_frame::{self, PHYFrame};
-use crate::asio_stream::InputAudioStream;
-use crate::utils::{
-    read_compressed_u8_2_data, read_data_2_compressed_u8, u8_2_code_rs_hexbit, Bit, Byte,
+use crate::acoustic_modem::phy_frame::{self, FrameStatus};
+use crate::acoustic_modem::preamble::{ChirpPreamble, Preamble};
+use crate::acoustic_modem::agc::{self, Agc, FrameLevel};
+use crate::acoustic_modem::preamble_detector::{
+    self, PreambleDetector, DEFAULT_PREAMBLE_THRESHOLD,
 };
+use crate::acoustic_modem::carrier_recovery::{CarrierTracking, CostasLoop};
+use crate::acoustic_modem::channel;
+use crate::acoustic_modem::constellation::{Constellation, DifferentialDetector};
+use crate::acoustic_modem::css::{self, CssConfig, CssDemodulator};
+use crate::acoustic_modem::dsss::DsssDemodulator;
+use crate::acoustic_modem::equalizer::{Equalizer, EqualizerConfig, EqualizerState};
+use crate::acoustic_modem::frame_codec::FrameCodec;
+use crate::acoustic_modem::front_end::{FrontEnd, FrontEndConfig};
+use crate::acoustic_modem::fsk::{FskConfig, FskDemodulator};
+use crate::acoustic_modem::modulation::Modulation;
+use crate::acoustic_modem::link_metrics::{CarrierAccumulator, FrameMetrics, SymbolDecision};
+use crate::acoustic_modem::ofdm::{OfdmConfig, OfdmDemodulator};
+use crate::acoustic_modem::pulse_shaping::PulseShape;
+use crate::acoustic_modem::soft_decision;
+use crate::acoustic_modem::timing_recovery::TimingRecovery;
+use crate::asio_stream::{self, InputAudioStream, LoopbackAudioStream};
+use crate::utils::Bit;
 use anyhow::Error;
 use cpal::traits::{DeviceTrait, HostTrait};
 use cpal::{Device, SampleRate, SupportedStreamConfig};
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::Write;
@@ -32,12 +50,28 @@ impl InputStreamConfig {
 }
 
 struct DemodulationConfig {
+    carrier_config: Vec<u32>,
     carrier_freq: Vec<u32>,
     sample_rate: u32,
+    modulation: Modulation,
+    constellation: Constellation,
+    pulse_shape: PulseShape,
+    // adaptive equalizer of the PSK / QAM symbols, None to disable it
+    equalizer: Option<EqualizerConfig>,
+    // Reed-Solomon code and size of the frames, None for the frames without encoding
+    frame_codec: Option<FrameCodec>,
+    redundant_periods: usize,
     ref_signal: Vec<Vec<f32>>,
     ref_signal_len: usize,
+    // receive filter chain, None to derive it from the carriers and the preamble
+    custom_front_end: Option<FrontEndConfig>,
+    front_end_config: FrontEndConfig,
+    preamble_waveform: Vec<f32>,
+    preamble_band: (f32, f32),
     preamble_len: usize,
-    preamble: Vec<f32>,
+    // the correlation peak of the preamble through a channel with gain 1
+    preamble_energy: f32,
+    preamble_detector: PreambleDetector,
 }
 
 unsafe impl Send for DemodulationConfig {}
@@ -45,20 +79,135 @@ unsafe impl Sync for DemodulationConfig {}
 
 impl DemodulationConfig {
     fn new(
+        carrier_config: Vec<u32>,
         carrier_freq: Vec<u32>,
         sample_rate: u32,
+        enable_ofdm: bool,
+        redundant_periods: usize,
         ref_signal: Vec<Vec<f32>>,
         ref_signal_len: usize,
     ) -> Self {
-        let preamble = phy_frame::gen_preamble(sample_rate);
-        // println!("preamble len: {}", preamble.len());
-        DemodulationConfig {
+        if enable_ofdm {
+            if let Err(err) = OfdmConfig::new(&carrier_config, sample_rate) {
+                panic!("[Demodulation2] {:?}: {}", carrier_config, err);
+            }
+        }
+        let mut config = DemodulationConfig {
+            carrier_config,
             carrier_freq,
             sample_rate,
+            modulation: if enable_ofdm { Modulation::Ofdm } else { Modulation::Psk },
+            constellation: Constellation::Bpsk,
+            pulse_shape: PulseShape::Rectangular,
+            equalizer: None,
+            frame_codec: if enable_ofdm { Some(FrameCodec::default()) } else { None },
+            redundant_periods,
             ref_signal,
             ref_signal_len,
-            preamble_len: preamble.len(),
-            preamble,
+            custom_front_end: None,
+            front_end_config: FrontEndConfig::bypass(),
+            preamble_waveform: phy_frame::gen_preamble(sample_rate),
+            preamble_band: ChirpPreamble::default().band(sample_rate),
+            preamble_len: 0,
+            preamble_energy: 0.0,
+            preamble_detector: PreambleDetector::new(&[1.0], DEFAULT_PREAMBLE_THRESHOLD),
+        };
+        config.update_front_end();
+        config
+    }
+
+    // the carriers are checked by `new`
+    fn ofdm_config(&self) -> OfdmConfig {
+        OfdmConfig::new(&self.carrier_config, self.sample_rate).unwrap()
+    }
+
+    // the band of the data symbols and the preamble in Hz
+    fn signal_band(&self) -> (f32, f32) {
+        let data_band = match &self.modulation {
+            Modulation::Ofdm => {
+                let ofdm_config = self.ofdm_config();
+                let spacing = ofdm_config.bin_freq(1);
+                (
+                    ofdm_config.bin_freq(ofdm_config.pilot_bins[0]) - spacing,
+                    ofdm_config.bin_freq(*ofdm_config.pilot_bins.last().unwrap()) + spacing,
+                )
+            }
+            Modulation::Css(spreading_factor) => {
+                let css_config = CssConfig::new(self.sample_rate, *spreading_factor);
+                (css::CSS_FREQ_LOW, css::CSS_FREQ_LOW + css_config.bandwidth())
+            }
+            Modulation::Psk | Modulation::Fsk | Modulation::Dsss(_) => {
+                // the main lobe and the first side lobes of the PSK symbols / DSSS chips, or the FSK
+                // tones: the symbols are rectangular, a narrower band smears them into each other
+                let symbol_rate = self.sample_rate as f32 / self.ref_signal_len as f32;
+                (
+                    self.carrier_freq[0] as f32 - 2.0 * symbol_rate,
+                    *self.carrier_freq.last().unwrap() as f32 + 2.0 * symbol_rate,
+                )
+            }
+        };
+        (
+            data_band.0.min(self.preamble_band.0).max(0.0),
+            data_band.1.max(self.preamble_band.1),
+        )
+    }
+
+    // the receive filter chain follows the carriers and the preamble, the matched filter of the
+    // preamble is the preamble through the chain
+    fn update_front_end(&mut self) {
+        self.front_end_config = self.custom_front_end.clone().unwrap_or_else(|| {
+            FrontEndConfig::new(self.signal_band(), self.sample_rate)
+        });
+        let preamble = FrontEnd::new(&self.front_end_config, self.sample_rate)
+            .process(&self.preamble_waveform);
+        self.preamble_len = preamble.len();
+        self.preamble_energy = dot_product(&preamble, &preamble);
+        self.preamble_detector =
+            PreambleDetector::new(&preamble, self.preamble_detector.threshold());
+    }
+
+    fn build_symbol_demodulator(&self) -> Box<dyn SymbolDemodulator + Send> {
+        match &self.modulation {
+            Modulation::Ofdm => {
+                let mut ofdm_config = self.ofdm_config();
+                ofdm_config.constellation = self.constellation;
+                ofdm_config.pulse_shape = self.pulse_shape;
+                Box::new(OfdmDemodulator::new(ofdm_config))
+            }
+            Modulation::Css(spreading_factor) => {
+                let css_config = CssConfig::new(self.sample_rate, *spreading_factor);
+                Box::new(CssDemodulator::new(css_config))
+            }
+            Modulation::Fsk => {
+                let fsk_config =
+                    FskConfig::new(&self.carrier_config, self.sample_rate, self.redundant_periods);
+                Box::new(FskDemodulator::new(fsk_config))
+            }
+            Modulation::Dsss(pn_code) => {
+                Box::new(DsssDemodulator::new(
+                    self.carrier_freq[0],
+                    self.sample_rate,
+                    self.ref_signal_len,
+                    pn_code,
+                    self.pulse_shape,
+                    &FrontEnd::new(&self.front_end_config, self.sample_rate),
+                ))
+            }
+            Modulation::Psk => {
+                assert!(
+                    !(self.equalizer.is_some() && self.constellation.is_differential()),
+                    "the equalizer needs a coherent constellation"
+                );
+                Box::new(PskDemodulator::new(
+                    &self.carrier_freq,
+                    self.sample_rate,
+                    self.ref_signal_len,
+                    self.constellation,
+                    self.pulse_shape,
+                    &FrontEnd::new(&self.front_end_config, self.sample_rate),
+                    self.equalizer.as_ref(),
+                ))
+            }
         }
     }
 }
@@ -103,9 +252,316 @@ where
         .fold(V::default(), |acc, x| acc + x)
 }
 
+// Demodulate the symbols of a frame, one symbol after another, after the preamble is detected.
+pub trait SymbolDemodulator {
+    // number of parallel bit streams, one frame on each of them
+    fn carrier_cnt(&self) -> usize;
+    // number of samples of each symbol
+    fn symbol_len(&self) -> usize;
+    // called when a new frame begins
+    // @param gain: amplitude gain of the channel, estimated from the preamble
+    fn reset(&mut self, gain: f32);
+    // return the bits of each carrier carried by the symbol in `window` (`symbol_len` samples)
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>>;
+    // state of the carrier recovery of each carrier, empty if the carriers are not tracked
+    fn carrier_tracking(&self) -> Vec<CarrierTracking> {
+        vec![]
+    }
+    // called after `demodulate`, with the window between the previous symbol and the current one
+    // return the samples by which the current symbol is sampled late, None if the symbol timing is
+    // not tracked or the symbols tell nothing about it
+    fn timing_error(&self, _mid_window: &[f32]) -> Option<f32> {
+        None
+    }
+    // samples by which the receive filter chain delays the symbols, the windows start this late
+    fn delay(&self) -> f32 {
+        0.0
+    }
+    // decision of the last symbol of each carrier for the link metrics, None for a symbol without
+    // bits, empty if the demodulator does not report its decisions
+    fn decisions(&self) -> Vec<Option<SymbolDecision>> {
+        vec![]
+    }
+    // convergence of the equalizer of each carrier, empty if the symbols are not equalized
+    fn equalizer_state(&self) -> Vec<EqualizerState> {
+        vec![]
+    }
+}
+
+// PSK / QAM: correlate the symbol with the reference sine (I) and cosine (Q) of each carrier
+// The references are multiplied by the envelope of the pulse shape (matched filter).
+pub struct PskDemodulator {
+    ref_sin: Vec<Vec<f32>>,
+    ref_cos: Vec<Vec<f32>>,
+    // references of the window between two symbols, for the timing error
+    ref_mid_sin: Vec<Vec<f32>>,
+    ref_mid_cos: Vec<Vec<f32>>,
+    // (I, Q) of the window between two symbols sampled at the right instant, as the linear combination
+    // of (I_prev, Q_prev, I, Q) of the two symbols
+    mid_weights: Vec<[[f32; 4]; 2]>,
+    // correlation of a symbol with amplitude 1 with its reference, i.e. half the energy of the envelope
+    ref_energy: f32,
+    // gain of the receive filter chain at each carrier
+    front_end_gain: Vec<f32>,
+    // group delay of the receive filter chain around the carriers
+    delay: f32,
+    constellation: Constellation,
+    half_distance: f32,
+    channel_gain: f32,
+    // one for each carrier, only used by differential constellations
+    differential_detectors: Vec<DifferentialDetector>,
+    // one for each carrier, only used by coherent constellations
+    carrier_loops: Vec<CostasLoop>,
+    // one for each carrier if the equalizer is enabled, instead of the carrier loops
+    equalizers: Vec<Equalizer>,
+    // (I, Q) of each carrier of the previous symbol and the current symbol, before the tracking
+    prev_values: Vec<(f32, f32)>,
+    values: Vec<(f32, f32)>,
+    decisions: Vec<Option<SymbolDecision>>,
+}
+
+impl PskDemodulator {
+    pub fn new(
+        carrier_freq: &Vec<u32>,
+        sample_rate: u32,
+        symbol_len: usize,
+        constellation: Constellation,
+        pulse_shape: PulseShape,
+        front_end: &FrontEnd,
+        equalizer: Option<&EqualizerConfig>,
+    ) -> Self {
+        let envelope = pulse_shape.window(symbol_len);
+        let half_len = symbol_len / 2;
+        let mut ref_sin = vec![];
+        let mut ref_cos = vec![];
+        let mut ref_mid_sin = vec![];
+        let mut ref_mid_cos = vec![];
+        let mut mid_weights = vec![];
+        let ref_energy = dot_product(&envelope, &envelope) / 2.0;
+        let mut front_end_gain = vec![];
+        let delay = carrier_freq
+            .iter()
+            .map(|&carrier| front_end.group_delay(carrier as f32))
+            .sum::<f32>()
+            / carrier_freq.len() as f32;
+        for &carrier in carrier_freq {
+            let omega = 2.0 * std::f32::consts::PI * carrier as f32 / sample_rate as f32;
+            // the references are shifted by the phase of the receive filter chain, and advanced by
+            // the delay of the windows
+            let (gain, phase) = front_end.response(carrier as f32);
+            let phase = phase + omega * delay;
+            ref_sin.push(
+                (0..symbol_len)
+                    .map(|t| (omega * t as f32 + phase).sin() * envelope[t])
+                    .collect(),
+            );
+            ref_cos.push(
+                (0..symbol_len)
+                    .map(|t| (omega * t as f32 + phase).cos() * envelope[t])
+                    .collect(),
+            );
+            // the second half of the previous symbol and the first half of the current symbol
+            let mid_phase = |t: usize| omega * (t as f32 - half_len as f32) + phase;
+            let mid_sin: Vec<f32> = (0..symbol_len)
+                .map(|t| mid_phase(t).sin() * envelope[(t + half_len) % symbol_len])
+                .collect();
+            let mid_cos: Vec<f32> = (0..symbol_len)
+                .map(|t| mid_phase(t).cos() * envelope[(t + half_len) % symbol_len])
+                .collect();
+            // the halves are not orthogonal if they are not whole periods of the carrier
+            let (first, second) = (0..half_len, half_len..symbol_len);
+            let weight = |a: &Vec<f32>, b: &Vec<f32>, range: std::ops::Range<usize>| {
+                dot_product(&a[range.clone()], &b[range]) / ref_energy
+            };
+            mid_weights.push([
+                [
+                    weight(&mid_sin, &mid_sin, first.clone()),
+                    weight(&mid_sin, &mid_cos, first.clone()),
+                    weight(&mid_sin, &mid_sin, second.clone()),
+                    weight(&mid_sin, &mid_cos, second.clone()),
+                ],
+                [
+                    weight(&mid_cos, &mid_sin, first.clone()),
+                    weight(&mid_cos, &mid_cos, first),
+                    weight(&mid_cos, &mid_sin, second.clone()),
+                    weight(&mid_cos, &mid_cos, second),
+                ],
+            ]);
+            ref_mid_sin.push(mid_sin);
+            ref_mid_cos.push(mid_cos);
+            front_end_gain.push(gain);
+        }
+
+        PskDemodulator {
+            ref_sin,
+            ref_cos,
+            ref_mid_sin,
+            ref_mid_cos,
+            mid_weights,
+            ref_energy,
+            front_end_gain,
+            delay,
+            constellation,
+            half_distance: constellation.half_distance(),
+            channel_gain: 1.0,
+            differential_detectors: vec![DifferentialDetector::default(); carrier_freq.len()],
+            carrier_loops: vec![
+                CostasLoop::new(sample_rate as f32 / symbol_len as f32);
+                carrier_freq.len()
+            ],
+            equalizers: equalizer
+                .map(|config| carrier_freq.iter().map(|_| Equalizer::new(config)).collect())
+                .unwrap_or_default(),
+            prev_values: vec![],
+            values: vec![],
+            decisions: vec![],
+        }
+    }
+}
+
+impl SymbolDemodulator for PskDemodulator {
+    fn carrier_cnt(&self) -> usize {
+        self.ref_sin.len()
+    }
+
+    fn symbol_len(&self) -> usize {
+        self.ref_sin[0].len()
+    }
+
+    fn reset(&mut self, gain: f32) {
+        self.channel_gain = gain;
+        for detector in self.differential_detectors.iter_mut() {
+            detector.reset();
+        }
+        for carrier_loop in self.carrier_loops.iter_mut() {
+            carrier_loop.reset();
+        }
+        for equalizer in self.equalizers.iter_mut() {
+            equalizer.reset();
+        }
+        self.prev_values.clear();
+        self.values.clear();
+    }
+
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>> {
+        let constellation = self.constellation;
+        self.prev_values = std::mem::take(&mut self.values);
+        self.decisions.clear();
+        (0..self.ref_sin.len())
+            .map(|i| {
+                let mut i_value = dot_product(window, &self.ref_sin[i]) / self.ref_energy;
+                let mut q_value = dot_product(window, &self.ref_cos[i]) / self.ref_energy;
+                self.values.push((i_value, q_value));
+                if constellation.is_differential() {
+                    let detector = &mut self.differential_detectors[i];
+                    let bits = detector.detect(constellation, i_value, q_value);
+                    self.decisions.push(detector.change().map(|change| SymbolDecision {
+                        received: change,
+                        decided: constellation.decide(change.0, change.1),
+                        half_distance: self.half_distance,
+                        phase_error: None,
+                        constellation,
+                    }));
+                    return bits;
+                }
+                // the phase alone decides PSK, the amplitude is only needed by QAM (and by the
+                // link metrics)
+                let gain = self.channel_gain * self.front_end_gain[i];
+                if let Some(equalizer) = self.equalizers.get_mut(i) {
+                    // the training symbols carry no bits
+                    let point = (i_value / gain, q_value / gain);
+                    let Some((i_value, q_value)) = equalizer.equalize(constellation, point) else {
+                        self.decisions.push(None);
+                        return vec![];
+                    };
+                    let bits = constellation.demap(i_value, q_value);
+                    self.decisions.push(Some(SymbolDecision {
+                        received: (i_value, q_value),
+                        decided: constellation.map(&bits),
+                        half_distance: self.half_distance,
+                        phase_error: None,
+                        constellation,
+                    }));
+                    return bits;
+                }
+                if constellation.need_amplitude() {
+                    i_value /= gain;
+                    q_value /= gain;
+                }
+                let (i_value, q_value) =
+                    self.carrier_loops[i].track(constellation, i_value, q_value);
+                let bits = constellation.demap(i_value, q_value);
+                let scale = if constellation.need_amplitude() { 1.0 } else { 1.0 / gain };
+                self.decisions.push(Some(SymbolDecision {
+                    received: (i_value * scale, q_value * scale),
+                    decided: constellation.map(&bits),
+                    half_distance: self.half_distance,
+                    phase_error: Some(self.carrier_loops[i].state().phase_error),
+                    constellation,
+                }));
+                bits
+            })
+            .collect()
+    }
+
+    // Gardner: sampled late by `tau`, the window between two symbols moves by `tau / symbol_len` of
+    // their difference towards the current symbol, e.g. from 0 between +1 and -1.
+    fn timing_error(&self, mid_window: &[f32]) -> Option<f32> {
+        // the echoes bias the timing error, the taps of the equalizer follow the drift instead
+        if self.prev_values.is_empty() || !self.equalizers.is_empty() {
+            return None;
+        }
+        let mut error = 0.0;
+        let mut transition = 0.0;
+        let mut energy = 0.0;
+        for i in 0..self.ref_sin.len() {
+            let mid_i = dot_product(mid_window, &self.ref_mid_sin[i]) / self.ref_energy;
+            let mid_q = dot_product(mid_window, &self.ref_mid_cos[i]) / self.ref_energy;
+            let (prev_i, prev_q) = self.prev_values[i];
+            let (i_value, q_value) = self.values[i];
+            let values = [prev_i, prev_q, i_value, q_value];
+            let [weight_i, weight_q] = &self.mid_weights[i];
+            let expected_i = dot_product(weight_i, &values);
+            let expected_q = dot_product(weight_q, &values);
+            let (diff_i, diff_q) = (prev_i - i_value, prev_q - q_value);
+            error += (mid_i - expected_i) * diff_i + (mid_q - expected_q) * diff_q;
+            transition += diff_i * diff_i + diff_q * diff_q;
+            energy += prev_i * prev_i + prev_q * prev_q + i_value * i_value + q_value * q_value;
+        }
+        // the timing is only seen at the transitions
+        if transition <= 0.0 || transition < energy / 2.0 {
+            return None;
+        }
+        Some(-error / transition * self.symbol_len() as f32)
+    }
+
+    fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    fn decisions(&self) -> Vec<Option<SymbolDecision>> {
+        self.decisions.clone()
+    }
+
+    fn equalizer_state(&self) -> Vec<EqualizerState> {
+        self.equalizers.iter().map(|equalizer| equalizer.state()).collect()
+    }
+
+    fn carrier_tracking(&self) -> Vec<CarrierTracking> {
+        if self.constellation.is_differential() || !self.equalizers.is_empty() {
+            return vec![];
+        }
+        self.carrier_loops
+            .iter()
+            .map(|carrier_loop| carrier_loop.state())
+            .collect()
+    }
+}
+
 pub struct Demodulation2 {
-    input_config: InputStreamConfig,
+    input_config: Option<InputStreamConfig>,
     demodulate_config: DemodulationConfig,
+    symbol_demodulator: Box<dyn SymbolDemodulator + Send>,
     writer: File,
 }
 
@@ -115,6 +571,7 @@ impl Demodulation2 {
         sample_rate: u32,
         output_file: &str,
         redundent_times: usize,
+        enable_ofdm: bool,
     ) -> Self {
         // let host = cpal::host_from_id(cpal::HostId::Asio).expect("failed to initialise ASIO host");
         let host = cpal::default_host();
@@ -138,9 +595,28 @@ impl Demodulation2 {
 
         let input_stream_config = InputStreamConfig::new(config, device);
 
+        let mut demodulator = Demodulation2::new_loopback(
+            carrier_config,
+            sample_rate,
+            output_file,
+            redundent_times,
+            enable_ofdm,
+        );
+        demodulator.input_config = Some(input_stream_config);
+        demodulator
+    }
+
+    // A demodulator without input device: the samples must be passed as `test_data` to `listening` / `simple_listen`.
+    pub fn new_loopback(
+        carrier_config: Vec<u32>,
+        sample_rate: u32,
+        output_file: &str,
+        redundent_times: usize,
+        enable_ofdm: bool,
+    ) -> Self {
         // sort carrier_freq in ascending order
         let mut carrier_freq = vec![];
-        for i in 0..carrier_config[2]{
+        for i in 0..carrier_config[2] {
             carrier_freq.push(carrier_config[0] + i * carrier_config[1]);
         }
         // carrier_freq.push(6000);
@@ -170,37 +646,147 @@ impl Demodulation2 {
             // ref_signal.push(ref_total);
         }
 
-        let demodulation_config =
-            DemodulationConfig::new(carrier_freq, sample_rate, ref_signal, ref_len);
+        let demodulation_config = DemodulationConfig::new(
+            carrier_config,
+            carrier_freq,
+            sample_rate,
+            enable_ofdm,
+            redundent_times,
+            ref_signal,
+            ref_len,
+        );
+        let symbol_demodulator = demodulation_config.build_symbol_demodulator();
 
         let writer = File::create(output_file).unwrap();
 
         Demodulation2 {
-            input_config: input_stream_config,
+            input_config: None,
             demodulate_config: demodulation_config,
+            symbol_demodulator,
             writer,
         }
     }
 
+    // BPSK by default, the modulator must use the same constellation
+    pub fn set_constellation(&mut self, constellation: Constellation) {
+        self.demodulate_config.constellation = constellation;
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // the modulation of the modulator (`Modulator::set_modulation`), PSK by default (OFDM with
+    // `enable_ofdm`). OFDM cannot be switched on or off here, it is chosen by `new`.
+    pub fn set_modulation(&mut self, modulation: Modulation) {
+        assert_eq!(
+            modulation == Modulation::Ofdm,
+            self.demodulate_config.modulation == Modulation::Ofdm,
+            "OFDM is chosen by `enable_ofdm` of `new`"
+        );
+        self.demodulate_config.modulation = modulation;
+        self.demodulate_config.update_front_end();
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // the chirp of `phy_frame::gen_preamble` by default, the modulator must use the same preamble
+    pub fn set_preamble(&mut self, preamble: &dyn Preamble) {
+        let sample_rate = self.demodulate_config.sample_rate;
+        self.demodulate_config.preamble_waveform = preamble.waveform(sample_rate);
+        self.demodulate_config.preamble_band = preamble.band(sample_rate);
+        self.demodulate_config.update_front_end();
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // receive filter chain before the preamble detection and the demodulation
+    // None (default) derives it from the carriers and the preamble, see `FrontEndConfig::new`
+    pub fn set_front_end(&mut self, front_end_config: Option<FrontEndConfig>) {
+        self.demodulate_config.custom_front_end = front_end_config;
+        self.demodulate_config.update_front_end();
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // adaptive equalizer of the PSK / QAM symbols against the echoes of the room, None by default
+    // The modulator must send the training symbols (`Modulator::set_training`), not used by OFDM,
+    // FSK, CSS and DSSS, nor by the differential constellations.
+    pub fn set_equalizer(&mut self, equalizer: Option<EqualizerConfig>) {
+        self.demodulate_config.equalizer = equalizer;
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // Reed-Solomon code and size of the frames of every modulation, None for the frames without
+    // encoding. The default codec with OFDM, None with the others. The modulator must use the same
+    // codec (`Modulator::set_frame_codec`), and `listening` takes `frame_codec.payload_len()` bits
+    // of each frame, or `FRAME_LENGTH_NO_ENCODING` without encoding.
+    pub fn set_frame_codec(&mut self, frame_codec: Option<FrameCodec>) {
+        self.demodulate_config.frame_codec = frame_codec;
+    }
+
+    pub fn front_end_config(&self) -> &FrontEndConfig {
+        &self.demodulate_config.front_end_config
+    }
+
+    // threshold of the normalized correlation (0..1) of the preamble, 0.3 by default
+    // A lower threshold detects weaker preambles, with more false alarms.
+    pub fn set_preamble_threshold(&mut self, threshold: f32) {
+        self.demodulate_config
+            .preamble_detector
+            .set_threshold(threshold);
+    }
+
+    // the pulse shape of the modulator, the references of PSK / DSSS are shaped by it (matched filter)
+    pub fn set_pulse_shape(&mut self, pulse_shape: PulseShape) {
+        self.demodulate_config.pulse_shape = pulse_shape;
+        self.symbol_demodulator = self.demodulate_config.build_symbol_demodulator();
+    }
+
+    // state of the carrier recovery of each carrier in the last received frame, for diagnostics
+    // Empty if the carriers are not tracked: OFDM uses the pilots, FSK / CSS / differential PSK
+    // do not need the carrier phase.
+    pub fn carrier_tracking(&self) -> Vec<CarrierTracking> {
+        self.symbol_demodulator.carrier_tracking()
+    }
+
+    // If `test_data` is not empty, the chunks in it are demodulated as a mono input (loopback),
+    // otherwise the input device is used.
+    // Returns the input stream and the number of interleaved channels in it.
+    fn create_input_stream(
+        &self,
+        test_data: Vec<Vec<f32>>,
+    ) -> (Box<dyn Stream<Item = Vec<f32>> + Unpin>, usize) {
+        if !test_data.is_empty() {
+            return (Box::new(LoopbackAudioStream::new(test_data)), 1);
+        }
+
+        let input_config = self
+            .input_config
+            .as_ref()
+            .expect("no input device: pass the samples by `test_data` in loopback mode");
+        (
+            Box::new(input_config.create_input_stream()),
+            input_config.config.channels() as usize,
+        )
+    }
+
     pub async fn simple_listen(
         &mut self,
         write_to_file: bool,
         debug_vec: &mut Vec<f32>,
         data_len: usize,
         padding_len: usize,
+        test_data: Vec<Vec<f32>>,
     ) -> Vec<u8> {
         let data_len = data_len;
 
-        let mut input_stream = self.input_config.create_input_stream();
+        let (mut input_stream, channels) = self.create_input_stream(test_data);
         let demodulate_config = &self.demodulate_config;
-        let alpha_check = 0.31;
-        let mut prev = 0.0;
+        let mut front_end = FrontEnd::new(
+            &demodulate_config.front_end_config,
+            demodulate_config.sample_rate,
+        );
+        let mut agc = Agc::new(demodulate_config.sample_rate);
 
         let mut demodulate_state = DemodulationState::DetectPreamble;
 
-        let mut avg_power = 0.0;
-        let power_lim_preamble = 0.5;
-        let factor = 1.0 / 64.0;
+        let preamble_detector = &demodulate_config.preamble_detector;
+        let threshold = preamble_detector.threshold();
 
         let mut tmp_buffer: VecDeque<f32> = VecDeque::with_capacity(
             15 * demodulate_config
@@ -213,7 +799,6 @@ impl Demodulation2 {
         let mut start_index = usize::MAX;
 
         let mut tmp_bits_data = Vec::with_capacity(data_len);
-        let channels = self.input_config.config.channels() as usize;
         // let mut res = vec![];
 
         while let Some(data) = input_stream.next().await {
@@ -223,35 +808,34 @@ impl Demodulation2 {
 
             // debug_vec.extend(data.clone().iter());
             tmp_buffer_len += data.len() / channels;
-            // let data = data.iter().map(|&sample| filter.run(sample)).collect();
-            move_data_into_buffer(data, &mut tmp_buffer, alpha_check, channels, &mut prev);
+            move_data_into_buffer(data, &mut tmp_buffer, &mut front_end, &mut agc, channels);
 
             if demodulate_state == DemodulationState::DetectPreamble {
                 if tmp_buffer_len <= demodulate_config.preamble_len + padding_len {
                     continue;
                 }
                 tmp_buffer.make_contiguous();
+                let samples = tmp_buffer.as_slices().0;
+                let correlation = preamble_detector.correlate(samples);
+                let metric = preamble_detector.normalize(samples, &correlation);
                 for i in 0..tmp_buffer_len - self.demodulate_config.preamble_len - 1 - padding_len {
                     // let window = tmp_buffer.range(i..i+demodulate_config.preamble_len);
-                    let window = &tmp_buffer.as_slices().0[i..i + demodulate_config.preamble_len];
-                    let dot_product = dot_product(window, &demodulate_config.preamble);
+                    let window = &samples[i..i + demodulate_config.preamble_len];
 
-                    if dot_product > avg_power * 2.0
-                        && dot_product > local_max
-                        && dot_product > power_lim_preamble
-                    {
+                    if metric[i] > local_max && metric[i] > threshold {
                         // println!("detected");
-                        local_max = dot_product;
+                        local_max = metric[i];
                         start_index = i + 1;
                         debug_vec.clear();
                         debug_vec.extend(window);
                     } else if start_index != usize::MAX
                         && i - start_index > demodulate_config.preamble_len
-                        && local_max > power_lim_preamble
+                        && local_max > threshold
                     {
                         local_max = 0.0;
                         start_index += demodulate_config.preamble_len - 1 + padding_len;
                         demodulate_state = demodulate_state.next();
+                        agc.hold(true);
                         println!("detected preamble");
                         break;
                     }
@@ -259,7 +843,7 @@ impl Demodulation2 {
             }
 
             if demodulate_state == DemodulationState::RecvFrame {
-                if tmp_buffer_len - start_index <= demodulate_config.ref_signal_len {
+                if tmp_buffer_len - start_index < demodulate_config.ref_signal_len {
                     continue;
                 }
                 tmp_buffer.make_contiguous();
@@ -287,6 +871,7 @@ impl Demodulation2 {
 
             if tmp_bits_data.len() >= data_len {
                 demodulate_state = DemodulationState::Stop;
+                end_frame(&mut agc);
             }
 
             let pop_times = if start_index == usize::MAX {
@@ -295,7 +880,7 @@ impl Demodulation2 {
                 start_index
             };
 
-            for i in 0..pop_times {
+            for _ in 0..pop_times {
                 tmp_buffer.pop_front();
             }
 
@@ -325,49 +910,56 @@ impl Demodulation2 {
         tmp_bits_data
     }
 
+    // return the link metrics of each detected preamble, see `link_metrics`
     pub async fn listening(
         &mut self,
         write_to_file: bool,
         data_len: usize,
         decoded_data: &mut Vec<u8>,
         debug_vec: &mut Vec<f32>,
-        test_data: Vec<Vec<f32>>
-    ) {
+        test_data: Vec<Vec<f32>>,
+    ) -> Vec<FrameMetrics> {
         // let data_len = data_len;
 
-        let mut input_stream = self.input_config.create_input_stream();
+        let (mut input_stream, channels) = self.create_input_stream(test_data);
         let demodulate_config = &self.demodulate_config;
-        let alpha_check = 0.31;
-        let mut prev = 0.0;
+        let mut front_end = FrontEnd::new(
+            &demodulate_config.front_end_config,
+            demodulate_config.sample_rate,
+        );
+        let mut agc = Agc::new(demodulate_config.sample_rate);
 
         let mut demodulate_state = DemodulationState::DetectPreamble;
 
-        let power_lim_preamble = 5.0;
+        let preamble_detector = &demodulate_config.preamble_detector;
+        let threshold = preamble_detector.threshold();
 
-        let mut tmp_buffer: VecDeque<f32> = VecDeque::with_capacity(
-            5 * demodulate_config
-                .preamble_len
-                .max(demodulate_config.ref_signal_len),
-        );
+        let symbol_len = self.symbol_demodulator.symbol_len();
+
+        let mut tmp_buffer: VecDeque<f32> =
+            VecDeque::with_capacity(5 * demodulate_config.preamble_len.max(symbol_len));
         let mut tmp_buffer_len = tmp_buffer.len();
 
+        // normalized correlation, correlation and sub-sample offset of the preamble peak
         let mut local_max = 0.0;
+        let mut peak_correlation = 0.0;
+        let mut peak_offset = 0.0;
         let mut start_index = usize::MAX;
 
-        let carrier_num = demodulate_config.carrier_freq.len();
+        let carrier_num = self.symbol_demodulator.carrier_cnt();
         // let mut tmp_bits_data = vec![vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 1, 0, 1, 1, 0, 1, 1, 1, 0, 0, 1, 1, 0, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1], vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 1, 0, 1, 1, 0, 0, 1, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 0, 1, 1, 1, 1, 0, 1, 0, 1, 1, 1, 1, 1, 0, 0, 1, 1, 0, 1, 1, 1]];
         let mut tmp_bits_data: Vec<Vec<u8>> = vec![Vec::with_capacity(data_len); carrier_num];
         let mut is_reboot = false;
-
-        let channels = self.input_config.config.channels() as usize;
+        let mut frame_metrics: Vec<FrameMetrics> = vec![];
+        let mut accumulators = vec![CarrierAccumulator::default(); carrier_num];
+        let mut timing_recovery = TimingRecovery::new(symbol_len);
 
         while let Some(data) = input_stream.next().await {
-        // for data in test_data{
             if demodulate_state == DemodulationState::Stop {
                 break;
             }
             tmp_buffer_len += data.len() / channels;
-            move_data_into_buffer(data, &mut tmp_buffer, alpha_check, channels, &mut prev);
+            move_data_into_buffer(data, &mut tmp_buffer, &mut front_end, &mut agc, channels);
             // println!("buffer len: {}", tmp_buffer_len);
             // tmp_buffer.extend(data.iter());
 
@@ -379,24 +971,42 @@ impl Demodulation2 {
                 // println!("start detect preamble");
                 // println!("for end: {}", tmp_buffer_len - self.demodulate_config.preamble_len-1);
                 tmp_buffer.make_contiguous();
+                let samples = tmp_buffer.as_slices().0;
+                let correlation = preamble_detector.correlate(samples);
+                let metric = preamble_detector.normalize(samples, &correlation);
                 for i in 0..tmp_buffer_len - demodulate_config.preamble_len - 1 {
                     // let window = tmp_buffer.range(i..i+demodulate_config.preamble_len);
-                    let window = &tmp_buffer.as_slices().0[i..i + demodulate_config.preamble_len];
-                    let dot_product = dot_product(window, &demodulate_config.preamble);
+                    let window = &samples[i..i + demodulate_config.preamble_len];
 
-                    if dot_product > local_max && dot_product > power_lim_preamble {
-                        local_max = dot_product;
-                        println!("detected, local max: {}", local_max);
+                    if metric[i] > local_max && metric[i] > threshold {
+                        local_max = metric[i];
+                        peak_correlation = correlation[i];
+                        peak_offset = preamble_detector::interpolate_peak(&correlation, i);
                         start_index = i + 1;
                         debug_vec.clear();
                         debug_vec.extend(window);
                     } else if start_index != usize::MAX
                         && i - start_index > demodulate_config.preamble_len
-                        && local_max > power_lim_preamble
+                        && local_max > threshold
                     {
+                        let gain = peak_correlation / demodulate_config.preamble_energy;
+                        frame_metrics.push(FrameMetrics {
+                            preamble_peak: local_max,
+                            channel_gain: gain,
+                            ..Default::default()
+                        });
+                        accumulators = vec![CarrierAccumulator::default(); carrier_num];
                         local_max = 0.0;
                         start_index += demodulate_config.preamble_len - 1;
+                        // the peak is between two samples, and the symbols are delayed by the
+                        // receive filter chain: the frame starts at a fraction of a sample
+                        let start = peak_offset + self.symbol_demodulator.delay();
+                        start_index = (start_index as isize + start.floor() as isize) as usize;
+                        peak_offset = start - start.floor();
                         demodulate_state = demodulate_state.next();
+                        agc.hold(true);
+                        self.symbol_demodulator.reset(gain);
+                        timing_recovery.reset(peak_offset);
                         // println!("detected preamble");
                         // println!("start index: {}, tmp buffer len: {}", start_index, tmp_buffer_len);
                         break;
@@ -407,30 +1017,33 @@ impl Demodulation2 {
 
             if demodulate_state == DemodulationState::RecvFrame {
                 if tmp_buffer_len < start_index
-                    || tmp_buffer_len - start_index <= demodulate_config.ref_signal_len
+                    || tmp_buffer_len - start_index < symbol_len
                 {
                     // println!("tmp buffer is not long enough");
                     continue;
                 }
                 tmp_buffer.make_contiguous();
 
-                while tmp_buffer_len - start_index >= demodulate_config.ref_signal_len
+                while start_index + symbol_len <= tmp_buffer_len
                     && tmp_bits_data[0].len() < data_len
                 {
-                    let window = &tmp_buffer.as_slices().0
-                                [start_index..start_index + demodulate_config.ref_signal_len];
-                    for i in 0..carrier_num{
-                        let dot_product = dot_product(
-                            window,
-                            &self.demodulate_config.ref_signal[i],
-                        );
-                        tmp_bits_data[i].push(if dot_product >= 0.0 { 0 } else { 1 });
+                    let samples = tmp_buffer.as_slices().0;
+                    let window = timing_recovery.window(samples, start_index);
+                    let bits = self.symbol_demodulator.demodulate(&window);
+                    for i in 0..carrier_num {
+                        tmp_bits_data[i].extend(bits[i].iter());
                     }
-                    debug_vec.extend(
-                        window,
-                    );
-                    start_index += demodulate_config.ref_signal_len;
-                    
+                    let decisions = self.symbol_demodulator.decisions();
+                    for (accumulator, decision) in accumulators.iter_mut().zip(decisions) {
+                        if let Some(decision) = decision {
+                            accumulator.add(&decision);
+                        }
+                    }
+                    let timing_error = self
+                        .symbol_demodulator
+                        .timing_error(&timing_recovery.mid_window(samples, start_index));
+                    debug_vec.extend(window);
+                    start_index += timing_recovery.advance(timing_error);
                 }
             }
 
@@ -438,62 +1051,72 @@ impl Demodulation2 {
                 // demodulate_state = demodulate_state.return_detect_preamble();
                 is_reboot = true;
                 demodulate_state = demodulate_state.next();
+                let level = end_frame(&mut agc);
+                let mut carriers = vec![];
                 // demodulate_state = DemodulationState::Stop;
                 for i in 0..carrier_num{
+                    // the last symbol may carry padding bits
+                    tmp_bits_data[i].truncate(data_len);
                     println!("freq{}, received: {:?}", demodulate_config.carrier_freq[i], tmp_bits_data[i]);
-                    let mut length = 0;
-                    for j in 0..phy_frame::FRAME_LENGTH_LENGTH{
-                        length <<= 1;
-                        length += tmp_bits_data[i][j] as usize;
+                    // the soft bits of the decisions, the hard bits if the demodulator reports none
+                    let mut llrs = accumulators[i].llrs();
+                    if llrs.len() < data_len {
+                        llrs = soft_decision::hard_llrs(&tmp_bits_data[i]);
                     }
-                    if length > phy_frame::MAX_FRAME_DATA_LENGTH{
-                        println!("wrong length {}", length);
-                        continue;
-                    }
-                    println!("received length {}", length);
-                    decoded_data.extend_from_slice(&tmp_bits_data[i][phy_frame::FRAME_LENGTH_LENGTH..phy_frame::FRAME_LENGTH_LENGTH + length]);
-                    if write_to_file{
-                        let to_write =  &tmp_bits_data[i]
-                                                        .clone()
-                                                        .iter()
-                                                        .map(|x| *x + b'0')
-                                                        .collect::<Vec<u8>>();
-                        self.writer.write_all(to_write).unwrap();
-                    }
-
-                    // let result = decode(tmp_bits_data[i].clone());
-                    // tmp_bits_data[i].clear();
-                    // match result {
-                    //     Ok((vec, length)) => {
-                    //         println!("length: {}", length);
-                    //         if length > phy_frame::MAX_FRAME_DATA_LENGTH {
-                    //             println!("freq {}, wrong data length", demodulate_config.carrier_freq[i]);
-                    //         } else {
-                    //             let decompressed = read_compressed_u8_2_data(vec)[0..length].to_vec();
-
-                    //             if write_to_file {
-                    //                 let to_write = &decompressed
-                    //                     .clone()
-                    //                     .iter()
-                    //                     .map(|x| *x + b'0')
-                    //                     .collect::<Vec<u8>>();
-                    //                 // println!("to write: {:?}", to_write);
-                    //                 self.writer.write_all(to_write).unwrap();
-                    //             }
-                    //             decoded_data.extend_from_slice(&decompressed[0..length]);
-                    //             println!("freq {}, received", demodulate_config.carrier_freq[i]);
-                    //         }
-                    //     }
-
-                    //     Err(_) => {
-                    //         println!("Error: received invalid data");
-                    //     }
-                    // };
+                    llrs.truncate(data_len);
+                    let result = match &demodulate_config.frame_codec {
+                        // the frames are built by `PHYFrame::new_with_codec` (RS encoded)
+                        Some(frame_codec) => frame_codec
+                            .decode(&llrs)
+                            .map(|(data, corrections)| (data, Some(corrections))),
+                        // the frames are built by `PHYFrame::new_no_encoding`
+                        None => decode_no_encoding(&llrs).map(|data| (data, None)),
+                    };
+                    tmp_bits_data[i].clear();
+                    carriers.push(match &result {
+                        Ok((_, corrections)) => {
+                            accumulators[i].finish(llrs, FrameStatus::Ok, *corrections)
+                        }
+                        Err(e) => accumulators[i].finish(llrs, FrameStatus::of_error(e), None),
+                    });
+
+                    match result {
+                        Ok((decompressed, _)) => {
+                            println!("received length {}", decompressed.len());
+                            if write_to_file {
+                                let to_write = &decompressed
+                                    .clone()
+                                    .iter()
+                                    .map(|x| *x + b'0')
+                                    .collect::<Vec<u8>>();
+                                self.writer.write_all(to_write).unwrap();
+                            }
+                            decoded_data.extend_from_slice(&decompressed);
+                        }
+
+                        Err(e) => {
+                            println!("freq {}, Error: {}", demodulate_config.carrier_freq[i], e);
+                        }
+                    };
+                }
+                let equalizers = self.symbol_demodulator.equalizer_state();
+                for (carrier, equalizer) in carriers.iter_mut().zip(equalizers) {
+                    carrier.equalizer = Some(equalizer);
+                }
+                if let Some(metrics) = frame_metrics.last_mut() {
+                    metrics.level = level;
+                    metrics.carriers = carriers;
+                    println!("[listening] {:?}", metrics);
                 }
             }
 
             let pop_times = if start_index == usize::MAX {
                 tmp_buffer_len - demodulate_config.preamble_len + 1
+            } else if demodulate_state == DemodulationState::RecvFrame {
+                // the samples before the current symbol are kept for the timing recovery
+                start_index
+                    .saturating_sub(timing_recovery.margin())
+                    .min(tmp_buffer_len)
             } else {
                 start_index
             };
@@ -506,53 +1129,108 @@ impl Demodulation2 {
                 is_reboot = false;
                 usize::MAX
             } else {
-                0
+                start_index - pop_times
             };
             tmp_buffer_len = tmp_buffer.len();
             // println!("tmp bit len: {:?}", tmp_bits_data[0].len());
             // println!("buffer len: {}", tmp_buffer_len);
         }
+
+        frame_metrics
     }
-}
 
-fn decode(input_data: Vec<Bit>) -> Result<(Vec<Byte>, usize), Error> {
-    // println!(
-    //     "input data: {:?}, data length: {}",
-    //     input_data,
-    //     input_data.len()
-    // );
-    let compressed = read_data_2_compressed_u8(input_data.clone());
-    let hexbits = u8_2_code_rs_hexbit(read_data_2_compressed_u8(input_data));
+    // demodulate a recording instead of the input device, return the decoded data bits
+    // @param data_len: the number of bits of each frame, see `listening`
+    pub async fn decode_wav(
+        &mut self,
+        filename: &str,
+        data_len: usize,
+        write_to_file: bool,
+    ) -> Result<Vec<Bit>, Error> {
+        let (samples, sample_rate) = asio_stream::read_wav_into_vec(filename).await?;
+        let samples = if sample_rate != self.demodulate_config.sample_rate {
+            println!(
+                "[decode_wav] resample from {} to {}",
+                sample_rate, self.demodulate_config.sample_rate
+            );
+            channel::resample(
+                &samples,
+                sample_rate as f64 / self.demodulate_config.sample_rate as f64,
+            )
+        } else {
+            samples
+        };
+
+        // like the callbacks of a sound card, and some silence to flush the last frame
+        let mut test_data = LoopbackAudioStream::split(&samples, 512);
+        test_data.push(vec![0.0; self.symbol_demodulator.symbol_len() + agc::LOOKAHEAD]);
+
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let detected_frames = self
+            .listening(write_to_file, data_len, &mut decoded_data, &mut debug_vec, test_data)
+            .await
+            .len();
+        println!(
+            "[decode_wav] {} frames detected, {} bits decoded",
+            detected_frames,
+            decoded_data.len()
+        );
 
-    // println!("hexbits: {:?}, hexbit length: {}", hexbits, hexbits.len());
-    let decoded = PHYFrame::payload_2_data(hexbits.clone());
+        Ok(decoded_data)
+    }
+}
 
-    let decoded_data = decoded.unwrap().0;
+// decode the LLRs of a frame without encoding, return the data bits
+// The CRC is the only check of the frame, the hard bits are not flipped by Chase decoding.
+pub fn decode_no_encoding(llrs: &[f32]) -> Result<Vec<Bit>, Error> {
+    decode_no_encoding_hard(soft_decision::hard_bits(llrs))
+}
 
-    let mut correct_num = 0;
-    for i in 0..decoded_data.len(){
-        if compressed[i] != decoded_data[i]{
-            correct_num += 1;
-        }
+fn decode_no_encoding_hard(input_data: Vec<Bit>) -> Result<Vec<Bit>, Error> {
+    if input_data.len() < phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING {
+        return Err(Error::msg(format!("frame too short: {}", input_data.len())));
     }
+    let mut length = 0;
+    for j in 0..phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING {
+        length <<= 1;
+        length += input_data[j] as usize;
+    }
+    // a frame longer than `MAX_FRAME_DATA_LENGTH` is received with a longer `data_len`
+    let data_end = phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING + length;
+    let crc_end = data_end + phy_frame::crc_length(length);
+    if crc_end > input_data.len() {
+        return Err(Error::msg(format!("wrong length {}", length)));
+    }
+    phy_frame::check_crc(&input_data[..data_end], &input_data[data_end..crc_end])?;
 
-    println!("correct num: {}", correct_num);
+    Ok(input_data[phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING..data_end].to_vec())
+}
 
-    PHYFrame::payload_2_data(hexbits)    
+// release the gain held since the preamble and report the level of the frame
+fn end_frame(agc: &mut Agc) -> FrameLevel {
+    let level = agc.take_level();
+    if level.is_clipping() {
+        println!("[Demodulation2] the input clips, turn the volume down: {:?}", level);
+    } else if level.is_too_quiet() {
+        println!("[Demodulation2] the input is too quiet, turn the volume up: {:?}", level);
+    }
+    agc.hold(false);
+    level
 }
 
+// the first channel of `data` through the receive filter chain and the AGC
 fn move_data_into_buffer(
     data: Vec<f32>,
     buffer: &mut VecDeque<f32>,
-    smooth_alpha: f32,
+    front_end: &mut FrontEnd,
+    agc: &mut Agc,
     channels: usize,
-    prev: &mut f32,
 ) {
     for (index, &i) in data.iter().enumerate() {
         if index % channels == 0 {
-            let processed_signal = i * smooth_alpha + *prev * (1.0 - smooth_alpha);
-            *prev = i;
-            buffer.push_back(processed_signal);
+            agc.observe_input(i);
+            buffer.push_back(agc.run(front_end.run(i)));
         }
     }
 }
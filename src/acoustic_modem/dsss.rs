@@ -0,0 +1,197 @@
+/*
+Direct-sequence spread spectrum
+
+Bits
+-> each bit XOR the chips of the PN code
+-> BPSK of the chips (`Modulator::modulate`)
+-> Output Signal
+
+Received Symbol (chips of one bit)
+-> correlation with the spread reference of bit 0 (despreading)
+-> Bits
+
+Different codes (e.g. the Gold codes of one family) let several links share the same carrier.
+*/
+use super::constellation::Constellation;
+use super::demodulation::{dot_product, SymbolDemodulator};
+use super::front_end::FrontEnd;
+use super::link_metrics::SymbolDecision;
+use super::pulse_shaping::PulseShape;
+use crate::utils::Bit;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PnCode {
+    // maximal length sequence of the LFSR degree (3..=10), 2^degree - 1 chips
+    MSequence(usize),
+    // Gold code of the preferred pair of the degree (5, 6, 7, 9, 10), 2^degree - 1 chips
+    // the index (0..2^degree - 1) is the relative shift of the pair, which selects the code in the family
+    Gold(usize, usize),
+    // Barker code of the length (2, 3, 4, 5, 7, 11, 13)
+    Barker(usize),
+}
+
+impl PnCode {
+    // chips as bits, chip 0 -> +1, chip 1 -> -1
+    pub fn chips(&self) -> Vec<Bit> {
+        match self {
+            PnCode::MSequence(degree) => lfsr_sequence(*degree, m_sequence_taps(*degree)),
+            PnCode::Gold(degree, index) => {
+                let (taps_1, taps_2) = gold_preferred_pair(*degree);
+                let sequence_1 = lfsr_sequence(*degree, taps_1);
+                let sequence_2 = lfsr_sequence(*degree, taps_2);
+                let len = sequence_1.len();
+                assert!(
+                    *index < len,
+                    "[PnCode] the index of the Gold code is out of range"
+                );
+                (0..len)
+                    .map(|i| sequence_1[i] ^ sequence_2[(i + index) % len])
+                    .collect()
+            }
+            PnCode::Barker(len) => match len {
+                2 => vec![0, 1],
+                3 => vec![0, 0, 1],
+                4 => vec![0, 0, 1, 0],
+                5 => vec![0, 0, 0, 1, 0],
+                7 => vec![0, 0, 0, 1, 1, 0, 1],
+                11 => vec![0, 0, 0, 1, 1, 1, 0, 1, 1, 0, 1],
+                13 => vec![0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 0, 1, 0],
+                _ => panic!("[PnCode] no Barker code of length {}", len),
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chips().len()
+    }
+}
+
+// feedback taps of a primitive polynomial of each degree
+fn m_sequence_taps(degree: usize) -> &'static [usize] {
+    match degree {
+        3 => &[3, 2],
+        4 => &[4, 3],
+        5 => &[5, 3],
+        6 => &[6, 5],
+        7 => &[7, 6],
+        8 => &[8, 6, 5, 4],
+        9 => &[9, 5],
+        10 => &[10, 7],
+        _ => panic!("[PnCode] no m-sequence of degree {}", degree),
+    }
+}
+
+fn gold_preferred_pair(degree: usize) -> (&'static [usize], &'static [usize]) {
+    match degree {
+        5 => (&[5, 3], &[5, 4, 3, 2]),
+        6 => (&[6, 1], &[6, 5, 2, 1]),
+        7 => (&[7, 3], &[7, 3, 2, 1]),
+        9 => (&[9, 4], &[9, 6, 4, 3]),
+        10 => (&[10, 3], &[10, 8, 3, 2]),
+        _ => panic!("[PnCode] no Gold code of degree {}", degree),
+    }
+}
+
+// one period of the Fibonacci LFSR, starting from the all-one state
+fn lfsr_sequence(degree: usize, taps: &[usize]) -> Vec<Bit> {
+    let mut state = vec![1 as Bit; degree];
+    let mut sequence = Vec::with_capacity((1 << degree) - 1);
+    for _ in 0..(1 << degree) - 1 {
+        sequence.push(state[degree - 1]);
+        let feedback = taps.iter().fold(0, |acc, &tap| acc ^ state[tap - 1]);
+        state.rotate_right(1);
+        state[0] = feedback;
+    }
+    sequence
+}
+
+// each bit is sent as the chips of the code, inverted for bit 1
+pub fn spread(bits: &Vec<Bit>, pn_code: &PnCode) -> Vec<Bit> {
+    let chips = pn_code.chips();
+    bits.iter()
+        .flat_map(|&bit| chips.iter().map(move |&chip| chip ^ bit))
+        .collect()
+}
+
+pub struct DsssDemodulator {
+    // the received waveform of bit 0, after the receive filter chain
+    ref_signal: Vec<f32>,
+    // group delay of the receive filter chain at the carrier
+    delay: f32,
+    // correlation of bit 0 through the receive filter chain, and the gain of the channel
+    ref_correlation: f32,
+    channel_gain: f32,
+    correlation: f32,
+}
+
+impl DsssDemodulator {
+    // @param chip_len: samples of each chip, the same as a PSK symbol
+    // @param pulse_shape: envelope of each chip, the same as the modulator
+    pub fn new(
+        carrier_freq: u32,
+        sample_rate: u32,
+        chip_len: usize,
+        pn_code: &PnCode,
+        pulse_shape: PulseShape,
+        front_end: &FrontEnd,
+    ) -> Self {
+        let delay = front_end.group_delay(carrier_freq as f32);
+        let omega = 2.0 * std::f32::consts::PI * carrier_freq as f32 / sample_rate as f32;
+        // the windows start `delay` samples late, the phase of the chain is taken back by it
+        let (gain, phase) = front_end.response(carrier_freq as f32);
+        let phase = phase + omega * delay;
+        let envelope = pulse_shape.window(chip_len);
+        let ref_chip: Vec<f32> = (0..chip_len)
+            .map(|t| (omega * t as f32 + phase).sin() * envelope[t])
+            .collect();
+
+        let mut ref_signal = vec![];
+        for chip in pn_code.chips() {
+            let sign = if chip == 0 { 1.0 } else { -1.0 };
+            ref_signal.extend(ref_chip.iter().map(|x| x * sign));
+        }
+        let ref_correlation = gain * dot_product(&ref_signal, &ref_signal);
+        DsssDemodulator {
+            ref_signal,
+            delay,
+            ref_correlation,
+            channel_gain: 1.0,
+            correlation: 0.0,
+        }
+    }
+}
+
+impl SymbolDemodulator for DsssDemodulator {
+    fn carrier_cnt(&self) -> usize {
+        1
+    }
+
+    fn symbol_len(&self) -> usize {
+        self.ref_signal.len()
+    }
+
+    // the gain is only needed by the link metrics
+    fn reset(&mut self, gain: f32) {
+        self.channel_gain = gain;
+    }
+
+    fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>> {
+        self.correlation = dot_product(window, &self.ref_signal);
+        vec![vec![if self.correlation >= 0.0 { 0 } else { 1 }]]
+    }
+
+    fn decisions(&self) -> Vec<Option<SymbolDecision>> {
+        let value = self.correlation / (self.channel_gain * self.ref_correlation);
+        vec![Some(SymbolDecision {
+            received: (value, 0.0),
+            decided: (if value >= 0.0 { 1.0 } else { -1.0 }, 0.0),
+            half_distance: 1.0,
+            phase_error: None,
+            constellation: Constellation::Bpsk,
+        })]
+    }
+}
@@ -0,0 +1,280 @@
+/*
+Adaptive channel equalizer of the PSK / QAM symbols
+
+Received point of each symbol of a carrier (matched filter, divided by the channel gain)
+-> feedforward taps over the last received points, feedback taps over the last decisions
+-> equalized point -> nearest constellation point
+-> error against the known training symbols after the preamble, then against the decisions
+   (decision-directed), the taps are adapted by LMS or RLS
+
+The echoes of a room smear each symbol into the next ones: the window of a symbol also holds the
+tail of the symbols before it, which the feedback taps subtract. The windows of the echoes are not
+whole periods of the carrier, so I and Q leak into each other: each point and decision is also
+weighed by its conjugate (widely linear). The taps follow the carrier phase and the drift of the
+sampling instant as well, the Costas loop and the timing recovery are not used with the equalizer.
+*/
+use super::constellation::Constellation;
+use super::dsss::PnCode;
+use rustfft::num_complex::Complex;
+
+// the inverse correlation matrix of RLS starts at I / RLS_DELTA
+const RLS_DELTA: f32 = 0.01;
+// against the division by 0 of the normalized LMS
+const LMS_EPSILON: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Adaptation {
+    // normalized LMS, the step (0..2) trades the speed of convergence against the noise of the taps
+    Lms { step: f32 },
+    // recursive least squares, converges in about twice the taps, the forgetting factor (0..1)
+    // trades the tracking of a changing channel against the noise of the taps
+    Rls { forgetting: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqualizerConfig {
+    pub adaptation: Adaptation,
+    // taps over the received points, the current one first
+    pub feedforward_taps: usize,
+    // taps over the decisions of the symbols before the current one
+    pub feedback_taps: usize,
+    // known symbols after the preamble, the modulator must send as many (`Modulator::set_training`)
+    pub training_len: usize,
+}
+
+impl EqualizerConfig {
+    pub fn lms(training_len: usize) -> Self {
+        EqualizerConfig {
+            adaptation: Adaptation::Lms { step: 0.2 },
+            feedforward_taps: 1,
+            feedback_taps: 2,
+            training_len,
+        }
+    }
+
+    pub fn rls(training_len: usize) -> Self {
+        EqualizerConfig {
+            adaptation: Adaptation::Rls { forgetting: 0.99 },
+            feedforward_taps: 2,
+            feedback_taps: 2,
+            training_len,
+        }
+    }
+
+    // each point and decision is weighed with its conjugate
+    fn taps(&self) -> usize {
+        2 * (self.feedforward_taps + self.feedback_taps)
+    }
+}
+
+// the training symbols: QPSK points of the m-sequence of degree 7, repeated if longer
+pub fn training_symbols(len: usize) -> Vec<(f32, f32)> {
+    let chips = PnCode::MSequence(7).chips();
+    (0..len)
+        .map(|k| {
+            let bits = [chips[2 * k % chips.len()], chips[(2 * k + 1) % chips.len()]];
+            Constellation::Qpsk.map(&bits)
+        })
+        .collect()
+}
+
+// the convergence of the equalizer of one carrier during a frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EqualizerState {
+    // |error|^2 of each training symbol, i.e. the learning curve
+    pub training_errors: Vec<f32>,
+    // mean |error|^2 of the decision-directed symbols
+    pub tracking_mse: Option<f32>,
+}
+
+impl EqualizerState {
+    // mean |error|^2 of the last quarter of the training, where the taps should have converged
+    pub fn training_mse(&self) -> Option<f32> {
+        let errors = &self.training_errors;
+        if errors.is_empty() {
+            return None;
+        }
+        let tail = &errors[errors.len() - errors.len().div_ceil(4)..];
+        Some(tail.iter().sum::<f32>() / tail.len() as f32)
+    }
+}
+
+// the equalizer of one carrier, y = w^H u with u = (received points, decisions)
+pub struct Equalizer {
+    config: EqualizerConfig,
+    training: Vec<Complex<f32>>,
+    weights: Vec<Complex<f32>>,
+    points: Vec<Complex<f32>>,
+    decisions: Vec<Complex<f32>>,
+    // inverse correlation matrix of the regressor, only used by RLS
+    inverse_correlation: Vec<Vec<Complex<f32>>>,
+    // index of the next symbol in the frame
+    symbol: usize,
+    training_errors: Vec<f32>,
+    tracking_error: f32,
+    tracking_cnt: usize,
+}
+
+impl Equalizer {
+    pub fn new(config: &EqualizerConfig) -> Self {
+        let training = training_symbols(config.training_len)
+            .into_iter()
+            .map(|(i, q)| Complex::new(i, q))
+            .collect();
+        let mut equalizer = Equalizer {
+            config: config.clone(),
+            training,
+            weights: vec![],
+            points: vec![],
+            decisions: vec![],
+            inverse_correlation: vec![],
+            symbol: 0,
+            training_errors: vec![],
+            tracking_error: 0.0,
+            tracking_cnt: 0,
+        };
+        equalizer.reset();
+        equalizer
+    }
+
+    // called when a new frame begins, the taps are trained again
+    pub fn reset(&mut self) {
+        let taps = self.config.taps();
+        let zero = Complex::new(0.0, 0.0);
+        // the current point passes through
+        self.weights = vec![zero; taps];
+        if self.config.feedforward_taps > 0 {
+            self.weights[0] = Complex::new(1.0, 0.0);
+        }
+        self.points = vec![zero; self.config.feedforward_taps];
+        self.decisions = vec![zero; self.config.feedback_taps];
+        self.inverse_correlation = (0..taps)
+            .map(|i| {
+                (0..taps)
+                    .map(|j| {
+                        if i == j {
+                            Complex::new(1.0 / RLS_DELTA, 0.0)
+                        } else {
+                            zero
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        self.symbol = 0;
+        self.training_errors.clear();
+        self.tracking_error = 0.0;
+        self.tracking_cnt = 0;
+    }
+
+    pub fn is_training(&self) -> bool {
+        self.symbol < self.training.len()
+    }
+
+    // equalize the received point and adapt the taps, None for a training symbol
+    pub fn equalize(
+        &mut self,
+        constellation: Constellation,
+        point: (f32, f32),
+    ) -> Option<(f32, f32)> {
+        if !self.points.is_empty() {
+            self.points.rotate_right(1);
+            self.points[0] = Complex::new(point.0, point.1);
+        }
+        let history = self.points.iter().chain(self.decisions.iter());
+        let regressor: Vec<Complex<f32>> = history
+            .clone()
+            .copied()
+            .chain(history.map(|x| x.conj()))
+            .collect();
+        let output: Complex<f32> = self
+            .weights
+            .iter()
+            .zip(&regressor)
+            .map(|(w, u)| w.conj() * u)
+            .sum();
+
+        let training = self.is_training();
+        let desired = if training {
+            self.training[self.symbol]
+        } else {
+            let (i, q) = constellation.decide(output.re, output.im);
+            Complex::new(i, q)
+        };
+        let error = desired - output;
+        self.adapt(&regressor, error);
+
+        if training {
+            self.training_errors.push(error.norm_sqr());
+            if self.symbol + 1 == self.training.len() {
+                println!(
+                    "[equalizer] training error {:.4} -> {:.4}, taps: {:?}",
+                    self.training_errors[0],
+                    self.state().training_mse().unwrap(),
+                    self.weights
+                );
+            }
+        } else {
+            self.tracking_error += error.norm_sqr();
+            self.tracking_cnt += 1;
+        }
+        if !self.decisions.is_empty() {
+            self.decisions.rotate_right(1);
+            self.decisions[0] = desired;
+        }
+        self.symbol += 1;
+
+        if training {
+            None
+        } else {
+            Some((output.re, output.im))
+        }
+    }
+
+    fn adapt(&mut self, regressor: &[Complex<f32>], error: Complex<f32>) {
+        match self.config.adaptation {
+            Adaptation::Lms { step } => {
+                let power: f32 = regressor.iter().map(|u| u.norm_sqr()).sum();
+                let step = step / (power + LMS_EPSILON);
+                for (w, u) in self.weights.iter_mut().zip(regressor) {
+                    *w += u * error.conj() * step;
+                }
+            }
+            Adaptation::Rls { forgetting } => {
+                let p = &mut self.inverse_correlation;
+                let taps = regressor.len();
+                // pi = P u, gain k = pi / (lambda + u^H pi)
+                let pi: Vec<Complex<f32>> = (0..taps)
+                    .map(|i| (0..taps).map(|j| p[i][j] * regressor[j]).sum())
+                    .collect();
+                let denominator: Complex<f32> = forgetting
+                    + regressor
+                        .iter()
+                        .zip(&pi)
+                        .map(|(u, x)| u.conj() * x)
+                        .sum::<Complex<f32>>();
+                let gain: Vec<Complex<f32>> = pi.iter().map(|x| x / denominator).collect();
+                for (w, k) in self.weights.iter_mut().zip(&gain) {
+                    *w += k * error.conj();
+                }
+                // P = (P - k pi^H) / lambda, P is Hermitian
+                for i in 0..taps {
+                    for j in 0..taps {
+                        p[i][j] = (p[i][j] - gain[i] * pi[j].conj()) / forgetting;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn state(&self) -> EqualizerState {
+        EqualizerState {
+            training_errors: self.training_errors.clone(),
+            tracking_mse: if self.tracking_cnt > 0 {
+                Some(self.tracking_error / self.tracking_cnt as f32)
+            } else {
+                None
+            },
+        }
+    }
+}
@@ -0,0 +1,307 @@
+/*
+Reed-Solomon frame codec
+
+Length and data of a frame
+-> [Length][Data][CRC], extended to the data hexbits of the blocks
+-> each block: Reed-Solomon code of `code_rs` over GF(64), the parity hexbits after the data
+-> the codewords of the blocks one after another
+-> optionally, the inner convolutional code (`convolutional::ConvCode`), decoded by Viterbi from
+   the LLRs before RS
+-> optionally, the interleaver of the sent bits (`interleaver::Interleaver`), undone on the LLRs
+   before the decoders
+
+The code sets the rate and the errors corrected in each block, the number of blocks sets the size
+of the frame: a larger frame for a good channel, a stronger code for a bad one. The default (one
+block of the medium code) is the frame of `PHYFrame::new`.
+*/
+use super::convolutional::ConvCode;
+use super::crc;
+use super::interleaver::Interleaver;
+use super::phy_frame;
+use super::soft_decision;
+use crate::utils::{self, Bit, Byte};
+use anyhow::{Error, Result};
+use code_rs::bits::Hexbit;
+use code_rs::coding::reed_solomon;
+
+// the Reed-Solomon codes of `code_rs`, (codeword, data) hexbits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RsCode {
+    // (24, 12, 13), corrects 6 hexbits
+    Short,
+    // (24, 16, 9), corrects 4 hexbits
+    Medium,
+    // (36, 20, 17), corrects 8 hexbits
+    Long,
+}
+
+impl RsCode {
+    pub fn codeword_len(&self) -> usize {
+        match self {
+            RsCode::Short | RsCode::Medium => 24,
+            RsCode::Long => 36,
+        }
+    }
+
+    pub fn data_len(&self) -> usize {
+        match self {
+            RsCode::Short => 12,
+            RsCode::Medium => 16,
+            RsCode::Long => 20,
+        }
+    }
+
+    // the wrong hexbits corrected in each codeword
+    pub fn correctable(&self) -> usize {
+        (self.codeword_len() - self.data_len()) / 2
+    }
+
+    // the data hexbits followed by the parity hexbits
+    fn encode(&self, data: &[Hexbit]) -> Vec<Hexbit> {
+        let mut codeword = data.to_vec();
+        codeword.resize(self.codeword_len(), Hexbit::new(0));
+        match self {
+            RsCode::Short => {
+                let mut array: [Hexbit; 24] = codeword.try_into().unwrap();
+                reed_solomon::short::encode(&mut array);
+                array.to_vec()
+            }
+            RsCode::Medium => {
+                let mut array: [Hexbit; 24] = codeword.try_into().unwrap();
+                reed_solomon::medium::encode(&mut array);
+                array.to_vec()
+            }
+            RsCode::Long => {
+                let mut array: [Hexbit; 36] = codeword.try_into().unwrap();
+                reed_solomon::long::encode(&mut array);
+                array.to_vec()
+            }
+        }
+    }
+
+    // the data hexbits and the corrected hexbits, None if there are too many errors
+    fn decode(&self, codeword: &[Hexbit]) -> Option<(Vec<Hexbit>, usize)> {
+        match self {
+            RsCode::Short => {
+                let mut array: [Hexbit; 24] = codeword.try_into().unwrap();
+                reed_solomon::short::decode(&mut array).map(|(data, err)| (data.to_vec(), err))
+            }
+            RsCode::Medium => {
+                let mut array: [Hexbit; 24] = codeword.try_into().unwrap();
+                reed_solomon::medium::decode(&mut array).map(|(data, err)| (data.to_vec(), err))
+            }
+            RsCode::Long => {
+                let mut array: [Hexbit; 36] = codeword.try_into().unwrap();
+                reed_solomon::long::decode(&mut array).map(|(data, err)| (data.to_vec(), err))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameCodec {
+    pub code: RsCode,
+    // codewords of each frame
+    pub blocks: usize,
+    // inner code of the RS codewords, None to send them as they are
+    pub inner: Option<ConvCode>,
+    // permutation of the sent bits against the bursts of errors, None to send them in order
+    pub interleaver: Option<Interleaver>,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec {
+            code: RsCode::Medium,
+            blocks: 1,
+            inner: None,
+            interleaver: None,
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn new(code: RsCode, blocks: usize) -> Self {
+        assert!(blocks > 0, "a frame needs at least one block");
+        FrameCodec {
+            code,
+            blocks,
+            inner: None,
+            interleaver: None,
+        }
+    }
+
+    // the bits of a frame after the encoding, i.e. the `data_len` of `Demodulation2::listening`
+    pub fn payload_len(&self) -> usize {
+        match self.inner {
+            Some(inner) => inner.encoded_len(self.rs_len()),
+            None => self.rs_len(),
+        }
+    }
+
+    // the bits of the RS codewords
+    fn rs_len(&self) -> usize {
+        self.blocks * self.code.codeword_len() * 6
+    }
+
+    // the bits of the length, the data and the CRC before the encoding
+    fn frame_len(&self) -> usize {
+        self.blocks * self.code.data_len() * 6
+    }
+
+    // bits of the length field, 8 bits up to 255 bits of data
+    pub fn length_len(&self) -> usize {
+        if self.frame_len() - 8 - phy_frame::FRAME_CRC_LENGTH <= 255 {
+            8
+        } else {
+            16
+        }
+    }
+
+    pub fn crc_len(&self) -> usize {
+        phy_frame::crc_length(self.frame_len() - self.length_len() - phy_frame::FRAME_CRC_LENGTH)
+    }
+
+    // the bits of data of a frame, a whole number of bytes
+    pub fn max_data_len(&self) -> usize {
+        self.frame_len() - self.length_len() - self.crc_len()
+    }
+
+    // the code rate, data bits over sent bits
+    pub fn rate(&self) -> f32 {
+        self.max_data_len() as f32 / self.payload_len() as f32
+    }
+
+    // encode `length` bits of `data` into the `payload_len` bits of a frame
+    pub fn encode(&self, length: usize, data: Vec<Byte>) -> Result<Vec<Bit>, Error> {
+        let max_data_len = self.max_data_len();
+        if length > max_data_len || data.len() * 8 > max_data_len.div_ceil(8) * 8 {
+            let err_msg = format!(
+                "Data length exceeds maximum frame data length: {}",
+                max_data_len
+            );
+            return Err(Error::msg(err_msg));
+        }
+
+        // length, data extended to the maximum, CRC
+        let mut bits = crc::to_bits(length as u32, self.length_len());
+        let mut data = utils::read_compressed_u8_2_data(data);
+        data.resize(max_data_len, 0);
+        bits.extend(data);
+        bits.extend(phy_frame::frame_crc(&bits, self.crc_len()));
+
+        let hexbits = bits_2_hexbits(&bits);
+        let mut payload = vec![];
+        for block in hexbits.chunks(self.code.data_len()) {
+            payload.extend(self.code.encode(block));
+        }
+        let bits = hexbits_2_bits(&payload);
+        let bits = match self.inner {
+            Some(inner) => inner.encode(&bits),
+            None => bits,
+        };
+        Ok(match self.interleaver {
+            Some(interleaver) => interleaver.interleave(&bits),
+            None => bits,
+        })
+    }
+
+    // decode the bits of a frame, return the data bits and the hexbits corrected by RS
+    pub fn decode_hard(&self, bits: Vec<Bit>) -> Result<(Vec<Bit>, usize), Error> {
+        if bits.len() < self.payload_len() {
+            return Err(Error::msg(format!("frame too short: {}", bits.len())));
+        }
+        let bits = self.deinterleave(&bits[..self.payload_len()]);
+        let bits = match self.inner {
+            Some(inner) => inner.decode(&soft_decision::hard_llrs(&bits)),
+            None => bits,
+        };
+        self.decode_rs(bits).and_then(|decoded| self.check_frame(decoded))
+    }
+
+    // decode the LLRs of a frame: by Viterbi then RS with the inner code, or the hard bits by RS
+    // and the least reliable bits flipped if it fails (`soft_decision::chase`), then check the CRC
+    pub fn decode(&self, llrs: &[f32]) -> Result<(Vec<Bit>, usize), Error> {
+        if llrs.len() < self.payload_len() {
+            return Err(Error::msg(format!("frame too short: {}", llrs.len())));
+        }
+        let llrs = self.deinterleave(&llrs[..self.payload_len()]);
+        match self.inner {
+            Some(inner) => self
+                .decode_rs(inner.decode(&llrs))
+                .and_then(|decoded| self.check_frame(decoded)),
+            None => soft_decision::chase(
+                &llrs,
+                |bits| self.decode_rs(bits),
+                |decoded| self.check_frame(decoded),
+            ),
+        }
+    }
+
+    fn deinterleave<T: Copy + Default>(&self, bits: &[T]) -> Vec<T> {
+        match self.interleaver {
+            Some(interleaver) => interleaver.deinterleave(bits),
+            None => bits.to_vec(),
+        }
+    }
+
+    // decode the bits of the RS codewords, return the bits of the length, the data and the CRC, and
+    // the hexbits corrected
+    fn decode_rs(&self, bits: Vec<Bit>) -> Result<(Vec<Bit>, usize), Error> {
+        if bits.len() < self.rs_len() {
+            return Err(Error::msg(format!("frame too short: {}", bits.len())));
+        }
+        let payload = bits_2_hexbits(&bits[..self.rs_len()]);
+        let mut hexbits = vec![];
+        let mut corrections = 0;
+        for codeword in payload.chunks(self.code.codeword_len()) {
+            let Some((data, corrected)) = self.code.decode(codeword) else {
+                return Err(Error::msg("RS decoding failed"));
+            };
+            hexbits.extend(data);
+            corrections += corrected;
+        }
+        Ok((hexbits_2_bits(&hexbits), corrections))
+    }
+
+    // check the CRC and the length of the decoded frame, return the data bits
+    fn check_frame(&self, decoded: (Vec<Bit>, usize)) -> Result<(Vec<Bit>, usize), Error> {
+        // length, data, CRC
+        let (bits, corrections) = decoded;
+        let data_end = self.length_len() + self.max_data_len();
+        phy_frame::check_crc(
+            &bits[..data_end],
+            &bits[data_end..data_end + self.crc_len()],
+        )?;
+        let length = bits[..self.length_len()]
+            .iter()
+            .fold(0, |length, &bit| length << 1 | bit as usize);
+        if length > self.max_data_len() {
+            return Err(Error::msg(format!("wrong length {}", length)));
+        }
+
+        Ok((
+            bits[self.length_len()..self.length_len() + length].to_vec(),
+            corrections,
+        ))
+    }
+}
+
+// 6 bits for each hexbit, most significant bit first, the last one padded with 0
+pub fn bits_2_hexbits(bits: &[Bit]) -> Vec<Hexbit> {
+    bits.chunks(6)
+        .map(|chunk| {
+            let value = (0..6).fold(0, |value, j| {
+                value << 1 | chunk.get(j).copied().unwrap_or(0)
+            });
+            Hexbit::new(value)
+        })
+        .collect()
+}
+
+pub fn hexbits_2_bits(hexbits: &[Hexbit]) -> Vec<Bit> {
+    hexbits
+        .iter()
+        .flat_map(|hexbit| (0..6).rev().map(move |j| (hexbit.bits() >> j) & 1))
+        .collect()
+}
@@ -0,0 +1,156 @@
+/*
+Receive filter chain
+
+Received samples
+-> DC blocker: y[n] = x[n] - x[n - 1] + r * y[n - 1] (DC offset of the sound card)
+-> high-pass (hum)
+-> band-pass around the carriers and the preamble (Butterworth high-pass + low-pass at the edges)
+-> notches (optional, e.g. a tone of a fan or the mains)
+-> automatic gain control (`agc::Agc`)
+-> preamble detection and demodulation
+
+Each stage is a biquad. The demodulators only know the response of the chain at their carriers
+(`FrontEnd::response`), the matched filter of the preamble is the preamble through the same chain.
+*/
+use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Type, Q_BUTTERWORTH_F32};
+
+// cutoff of the high-pass in Hz
+pub const DEFAULT_HIGH_PASS: f32 = 100.0;
+// the edges of the band-pass are widened by this ratio around the band of the signal
+const BAND_MARGIN: f32 = 0.7;
+// pole of the DC blocker, a notch of about 40Hz wide at 0Hz at 48kHz
+const DC_BLOCKER_POLE: f32 = 0.995;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrontEndConfig {
+    pub dc_blocker: bool,
+    // cutoff of the high-pass in Hz, None for no high-pass
+    pub high_pass: Option<f32>,
+    // lower and upper cutoff of the band-pass in Hz, None for no band-pass
+    pub band_pass: Option<(f32, f32)>,
+    // center frequency in Hz and Q of each notch
+    pub notches: Vec<(f32, f32)>,
+}
+
+impl FrontEndConfig {
+    // the chain for a signal in `band` (Hz): the high-pass and a band-pass with a margin around it
+    pub fn new(band: (f32, f32), sample_rate: u32) -> Self {
+        let nyquist = sample_rate as f32 / 2.0;
+        let low = band.0 * BAND_MARGIN;
+        let high = band.1 / BAND_MARGIN;
+        FrontEndConfig {
+            dc_blocker: true,
+            high_pass: Some(DEFAULT_HIGH_PASS),
+            band_pass: Some((low.max(DEFAULT_HIGH_PASS), high.min(0.9 * nyquist))),
+            notches: vec![],
+        }
+    }
+
+    // no filtering at all
+    pub fn bypass() -> Self {
+        FrontEndConfig {
+            dc_blocker: false,
+            high_pass: None,
+            band_pass: None,
+            notches: vec![],
+        }
+    }
+}
+
+pub struct FrontEnd {
+    sample_rate: u32,
+    coefficients: Vec<Coefficients<f32>>,
+    filters: Vec<DirectForm2Transposed<f32>>,
+}
+
+impl FrontEnd {
+    pub fn new(config: &FrontEndConfig, sample_rate: u32) -> Self {
+        let fs = sample_rate.hz();
+        let biquad = |filter: Type, freq: f32, q: f32| {
+            Coefficients::<f32>::from_params(filter, fs, freq.hz(), q)
+                .expect("[FrontEnd] the cutoff must be below the Nyquist frequency")
+        };
+
+        let mut coefficients = vec![];
+        if config.dc_blocker {
+            coefficients.push(Coefficients {
+                a1: -DC_BLOCKER_POLE,
+                a2: 0.0,
+                b0: 1.0,
+                b1: -1.0,
+                b2: 0.0,
+            });
+        }
+        if let Some(cutoff) = config.high_pass {
+            coefficients.push(biquad(Type::HighPass, cutoff, Q_BUTTERWORTH_F32));
+        }
+        if let Some((low, high)) = config.band_pass {
+            assert!(low < high, "[FrontEnd] the band-pass is empty");
+            // the high-pass already cuts below its own cutoff
+            if config.high_pass.is_none_or(|cutoff| low > cutoff) {
+                coefficients.push(biquad(Type::HighPass, low, Q_BUTTERWORTH_F32));
+            }
+            coefficients.push(biquad(Type::LowPass, high, Q_BUTTERWORTH_F32));
+        }
+        for &(freq, q) in &config.notches {
+            coefficients.push(biquad(Type::Notch, freq, q));
+        }
+
+        FrontEnd {
+            sample_rate,
+            filters: coefficients
+                .iter()
+                .map(|&c| DirectForm2Transposed::<f32>::new(c))
+                .collect(),
+            coefficients,
+        }
+    }
+
+    pub fn run(&mut self, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample, |x, filter| filter.run(x))
+    }
+
+    // the samples through a fresh chain, e.g. the preamble
+    pub fn process(&self, samples: &[f32]) -> Vec<f32> {
+        let mut filters: Vec<DirectForm2Transposed<f32>> = self
+            .coefficients
+            .iter()
+            .map(|&c| DirectForm2Transposed::<f32>::new(c))
+            .collect();
+        samples
+            .iter()
+            .map(|&sample| filters.iter_mut().fold(sample, |x, filter| filter.run(x)))
+            .collect()
+    }
+
+    // gain and phase shift of the chain at `freq`
+    pub fn response(&self, freq: f32) -> (f32, f32) {
+        let omega = 2.0 * std::f32::consts::PI * freq / self.sample_rate as f32;
+        let (mut gain, mut phase) = (1.0, 0.0);
+        for c in &self.coefficients {
+            // H(e^jw) = (b0 + b1 e^-jw + b2 e^-2jw) / (1 + a1 e^-jw + a2 e^-2jw)
+            let num_re = c.b0 + c.b1 * omega.cos() + c.b2 * (2.0 * omega).cos();
+            let num_im = -c.b1 * omega.sin() - c.b2 * (2.0 * omega).sin();
+            let den_re = 1.0 + c.a1 * omega.cos() + c.a2 * (2.0 * omega).cos();
+            let den_im = -c.a1 * omega.sin() - c.a2 * (2.0 * omega).sin();
+            gain *= (num_re.hypot(num_im)) / den_re.hypot(den_im);
+            phase += num_im.atan2(num_re) - den_im.atan2(den_re);
+        }
+        (gain, phase)
+    }
+
+    // group delay of the chain at `freq` in samples, the derivative of the phase shift
+    pub fn group_delay(&self, freq: f32) -> f32 {
+        // one thousandth of a bin of a 1024 FFT
+        let step = self.sample_rate as f32 / 1024e3;
+        let (_, before) = self.response(freq - step);
+        let (_, after) = self.response(freq + step);
+        let difference = (after - before + std::f32::consts::PI)
+            .rem_euclid(2.0 * std::f32::consts::PI)
+            - std::f32::consts::PI;
+        let omega_step = 2.0 * std::f32::consts::PI * 2.0 * step / self.sample_rate as f32;
+        -difference / omega_step
+    }
+}
@@ -0,0 +1,133 @@
+/*
+Bits
+-> one tone of the carriers for each symbol (phase continuous)
+-> Output Signal
+
+Received Symbol
+-> energy of each tone (Goertzel)
+-> Bits of the strongest tone
+*/
+use super::demodulation::SymbolDemodulator;
+use crate::utils::Bit;
+
+#[derive(Clone, Debug)]
+pub struct FskConfig {
+    pub sample_rate: u32,
+    // the symbol value `i` is sent on `tones[i]`
+    pub tones: Vec<u32>,
+    pub symbol_len: usize,
+}
+
+impl FskConfig {
+    // carrier_freq_config: [lowest carrier, interval, count], the same as `Modulator::new`
+    // The first 2^n carriers are used as the tones. Each symbol lasts `redundant_periods` periods
+    // of the interval, so that the tones are orthogonal to each other.
+    pub fn new(carrier_freq_config: &Vec<u32>, sample_rate: u32, redundant_periods: usize) -> Self {
+        let (carrier_low, carrier_interval, carrier_cnt) = (
+            carrier_freq_config[0],
+            carrier_freq_config[1],
+            carrier_freq_config[2],
+        );
+        assert!(
+            carrier_cnt >= 2,
+            "[FskConfig] FSK needs at least 2 carriers"
+        );
+        let tone_cnt = 1 << (31 - carrier_cnt.leading_zeros());
+        let tones = (0..tone_cnt)
+            .map(|i| carrier_low + i * carrier_interval)
+            .collect();
+
+        FskConfig {
+            sample_rate,
+            tones,
+            symbol_len: (sample_rate / carrier_interval) as usize * redundant_periods,
+        }
+    }
+
+    pub fn bits_per_symbol(&self) -> usize {
+        self.tones.len().trailing_zeros() as usize
+    }
+}
+
+// the last symbol is padded with 0
+pub fn modulate(config: &FskConfig, bits: &Vec<Bit>) -> Vec<f32> {
+    let bits_per_symbol = config.bits_per_symbol();
+    let mut modulated_signal = vec![];
+    let mut phase = 0.0_f64;
+
+    for chunk in bits.chunks(bits_per_symbol) {
+        let mut symbol = 0;
+        for j in 0..bits_per_symbol {
+            symbol = symbol << 1 | *chunk.get(j).unwrap_or(&0) as usize;
+        }
+        let phase_step =
+            2.0 * std::f64::consts::PI * config.tones[symbol] as f64 / config.sample_rate as f64;
+        for _ in 0..config.symbol_len {
+            modulated_signal.push(phase.sin() as f32);
+            phase = (phase + phase_step) % (2.0 * std::f64::consts::PI);
+        }
+    }
+
+    println!(
+        "[fsk modulate] {} tones, {} symbols, length {}",
+        config.tones.len(),
+        modulated_signal.len() / config.symbol_len,
+        modulated_signal.len()
+    );
+
+    return modulated_signal;
+}
+
+// energy of `freq` in the window
+pub fn goertzel(window: &[f32], freq: f32, sample_rate: u32) -> f32 {
+    let coeff = 2.0 * (2.0 * std::f32::consts::PI * freq / sample_rate as f32).cos();
+    let mut s_prev = 0.0;
+    let mut s_prev2 = 0.0;
+    for &x in window {
+        let s = x + coeff * s_prev - s_prev2;
+        s_prev2 = s_prev;
+        s_prev = s;
+    }
+    s_prev * s_prev + s_prev2 * s_prev2 - coeff * s_prev * s_prev2
+}
+
+// non-coherent detection: the tone with the largest energy wins
+pub struct FskDemodulator {
+    config: FskConfig,
+}
+
+impl FskDemodulator {
+    pub fn new(config: FskConfig) -> Self {
+        FskDemodulator { config }
+    }
+}
+
+impl SymbolDemodulator for FskDemodulator {
+    fn carrier_cnt(&self) -> usize {
+        1
+    }
+
+    fn symbol_len(&self) -> usize {
+        self.config.symbol_len
+    }
+
+    // the energy detection needs neither the phase nor the gain
+    fn reset(&mut self, _gain: f32) {}
+
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>> {
+        let mut symbol = 0;
+        let mut max_energy = -1.0;
+        for (i, &tone) in self.config.tones.iter().enumerate() {
+            let energy = goertzel(window, tone as f32, self.config.sample_rate);
+            if energy > max_energy {
+                max_energy = energy;
+                symbol = i;
+            }
+        }
+
+        let bits_per_symbol = self.config.bits_per_symbol();
+        vec![(0..bits_per_symbol)
+            .map(|j| ((symbol >> (bits_per_symbol - 1 - j)) & 1) as Bit)
+            .collect()]
+    }
+}
@@ -0,0 +1,72 @@
+/*
+Bit interleaver of a frame
+
+Coded bits of a frame (`frame_codec::FrameCodec`, after the inner code)
+-> permutation of the bits -> modulation
+LLRs of the received bits -> inverse permutation -> decoders
+
+The echoes and the transient noise of the room corrupt runs of consecutive bits. After the
+inverse permutation, the bits of a run are far apart: in different RS codewords, or isolated for
+the Viterbi decoder.
+
+Block: the bits are written in `rows` rows and sent column by column, the bits of a run are one
+row apart. With as many rows as RS blocks, consecutive bits go to different codewords.
+Convolutional: the bits are dealt to `branches` branches in turn, branch j delays its bits by
+j * `delay` bits of the branch. The delays wrap around at the end of the frame (tail-biting), so
+that the frame keeps its length. Consecutive bits are `branches * delay - 1` bits apart, and the
+bits of a run of up to `branches * delay` bits at least `branches - 1` apart, if the frame is longer
+than `branches * branches * delay` bits.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interleaver {
+    Block { rows: usize },
+    Convolutional { branches: usize, delay: usize },
+}
+
+impl Interleaver {
+    // the position in the frame of the k-th sent bit
+    pub fn permutation(&self, len: usize) -> Vec<usize> {
+        match *self {
+            Interleaver::Block { rows } => {
+                assert!(rows > 0, "[Interleaver] no rows");
+                let columns = len.div_ceil(rows);
+                // column by column, the last row may be shorter
+                (0..columns)
+                    .flat_map(|column| (0..rows).map(move |row| row * columns + column))
+                    .filter(|&k| k < len)
+                    .collect()
+            }
+            Interleaver::Convolutional { branches, delay } => {
+                assert!(branches > 0, "[Interleaver] no branches");
+                (0..len)
+                    .map(|k| {
+                        let branch = k % branches;
+                        // the bits of the branch, and the index of the k-th bit among them
+                        let branch_len = (len - branch).div_ceil(branches);
+                        let index = k / branches;
+                        let delayed =
+                            (index + branch_len - branch * delay % branch_len) % branch_len;
+                        delayed * branches + branch
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn interleave<T: Copy>(&self, bits: &[T]) -> Vec<T> {
+        self.permutation(bits.len())
+            .into_iter()
+            .map(|k| bits[k])
+            .collect()
+    }
+
+    // the inverse of `interleave`, for the bits or the LLRs
+    pub fn deinterleave<T: Copy + Default>(&self, bits: &[T]) -> Vec<T> {
+        let mut deinterleaved = vec![T::default(); bits.len()];
+        for (sent, k) in self.permutation(bits.len()).into_iter().enumerate() {
+            deinterleaved[k] = bits[sent];
+        }
+        deinterleaved
+    }
+}
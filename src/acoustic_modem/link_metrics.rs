@@ -0,0 +1,186 @@
+/*
+Link quality of the received frames
+
+Decision of each symbol of each carrier (`SymbolDemodulator::decisions`)
+-> the received point against the nearest constellation point
+-> per carrier: SNR from the error vector, margin to the decision boundary, variance of the phase
+   error of the carrier recovery
+-> LLR of each bit, scaled by the noise of the frame (`soft_decision`)
+-> per frame: the preamble peak and the channel gain, the input level of the frame, the carriers,
+   and whether the frame is decoded (`FrameStatus`) with how many Reed-Solomon corrections
+
+The SNR is estimated from the decisions, it is too high when the errors cross the decision boundary,
+i.e. below about 5dB for BPSK. A frame without error vector reports `MAX_SNR_DB`.
+*/
+use super::agc::FrameLevel;
+use super::constellation::Constellation;
+use super::equalizer::EqualizerState;
+use super::phy_frame::FrameStatus;
+use super::soft_decision;
+
+// the SNR of a frame without error vector, e.g. through a noiseless channel
+pub const MAX_SNR_DB: f32 = 60.0;
+
+// the decision of one symbol of one carrier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymbolDecision {
+    // the received point, scaled so that the constellation has a peak amplitude of 1
+    pub received: (f32, f32),
+    // the nearest constellation point
+    pub decided: (f32, f32),
+    // distance from a point to the decision boundary, see `Constellation::half_distance`
+    pub half_distance: f32,
+    // phase error of the carrier recovery in rad, None if the carrier phase is not tracked
+    pub phase_error: Option<f32>,
+    // the constellation of `decided`, to give the LLRs of the bits
+    pub constellation: Constellation,
+}
+
+impl SymbolDecision {
+    // see `Constellation::llrs`
+    pub fn llrs(&self, noise_var: f32) -> Vec<f32> {
+        self.constellation
+            .llrs(self.received.0, self.received.1, noise_var)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CarrierMetrics {
+    // symbols with a decision, 0 if the demodulator does not report them (FSK, CSS)
+    pub symbols: usize,
+    // estimated SNR in dB, from the error vector of the decisions
+    pub snr_db: Option<f32>,
+    // mean distance to the decision boundary, relative to the distance of the constellation point
+    // (`Constellation::boundary_distance`): 1 on the point, 0 on the boundary, above 1 outside of
+    // the outer points. The decisions are the nearest points, so it is never negative.
+    pub margin: Option<f32>,
+    // variance of the phase error in rad^2
+    pub phase_error_var: Option<f32>,
+    // power of the error vector, the noise which scales the LLRs
+    pub noise_var: Option<f32>,
+    // LLR of each bit of the frame (the length and the data), see `soft_decision`
+    pub llrs: Vec<f32>,
+    // a corrupted frame (`FrameStatus::CrcMismatch`) is not added to the decoded data
+    pub status: FrameStatus,
+    // symbols corrected by Reed-Solomon, None if the frame is not encoded or not decoded
+    pub rs_corrections: Option<usize>,
+    // convergence of the equalizer, None if it is disabled
+    pub equalizer: Option<EqualizerState>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameMetrics {
+    // normalized correlation (0..1) of the preamble peak
+    pub preamble_peak: f32,
+    // gain of the channel, from the preamble
+    pub channel_gain: f32,
+    pub level: FrameLevel,
+    // one for each carrier, empty if the frame is not received to the end
+    pub carriers: Vec<CarrierMetrics>,
+}
+
+impl FrameMetrics {
+    // the worst SNR of the carriers
+    pub fn snr_db(&self) -> Option<f32> {
+        self.carriers
+            .iter()
+            .filter_map(|carrier| carrier.snr_db)
+            .reduce(f32::min)
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        !self.carriers.is_empty()
+            && self
+                .carriers
+                .iter()
+                .all(|carrier| carrier.status == FrameStatus::Ok)
+    }
+}
+
+// the decisions of one carrier during a frame
+#[derive(Clone, Debug, Default)]
+pub struct CarrierAccumulator {
+    symbols: usize,
+    signal_power: f32,
+    error_power: f32,
+    margin: f32,
+    phase_errors: Vec<f32>,
+    decisions: Vec<SymbolDecision>,
+}
+
+impl CarrierAccumulator {
+    pub fn add(&mut self, decision: &SymbolDecision) {
+        let (received, decided) = (decision.received, decision.decided);
+        let error = (received.0 - decided.0).hypot(received.1 - decided.1);
+        self.symbols += 1;
+        self.signal_power += decided.0 * decided.0 + decided.1 * decided.1;
+        self.error_power += error * error;
+        self.margin += decision.constellation.boundary_distance(received, decided)
+            / decision.half_distance;
+        if let Some(phase_error) = decision.phase_error {
+            self.phase_errors.push(phase_error);
+        }
+        self.decisions.push(*decision);
+    }
+
+    // the noise of the frame, None if there is no decision
+    pub fn noise_var(&self) -> Option<f32> {
+        if self.symbols == 0 {
+            return None;
+        }
+        Some((self.error_power / self.symbols as f32).max(soft_decision::MIN_NOISE_VAR))
+    }
+
+    // the LLRs of the bits of all the decisions, empty if there is no decision
+    pub fn llrs(&self) -> Vec<f32> {
+        match self.noise_var() {
+            Some(noise_var) => self
+                .decisions
+                .iter()
+                .flat_map(|decision| decision.llrs(noise_var))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // @param llrs: the LLRs given to the frame decoder
+    pub fn finish(
+        &self,
+        llrs: Vec<f32>,
+        status: FrameStatus,
+        rs_corrections: Option<usize>,
+    ) -> CarrierMetrics {
+        let phase_error_var = if self.phase_errors.is_empty() {
+            None
+        } else {
+            let cnt = self.phase_errors.len() as f32;
+            let mean = self.phase_errors.iter().sum::<f32>() / cnt;
+            Some(
+                self.phase_errors
+                    .iter()
+                    .map(|x| (x - mean).powi(2))
+                    .sum::<f32>()
+                    / cnt,
+            )
+        };
+        let (snr_db, margin) = if self.symbols == 0 {
+            (None, None)
+        } else {
+            (
+                Some((10.0 * (self.signal_power / self.error_power).log10()).min(MAX_SNR_DB)),
+                Some(self.margin / self.symbols as f32),
+            )
+        };
+        CarrierMetrics {
+            symbols: self.symbols,
+            snr_db,
+            margin,
+            phase_error_var,
+            noise_var: self.noise_var(),
+            llrs,
+            status,
+            rs_corrections,
+            equalizer: None,
+        }
+    }
+}
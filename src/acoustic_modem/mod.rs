@@ -1,3 +1,23 @@
+pub mod agc;
+pub mod carrier_recovery;
+pub mod channel;
+pub mod constellation;
+pub mod convolutional;
+pub mod crc;
+pub mod css;
 pub mod demodulation;
+pub mod dsss;
+pub mod equalizer;
+pub mod front_end;
+pub mod frame_codec;
+pub mod fsk;
+pub mod interleaver;
+pub mod link_metrics;
 pub mod modulation;
-pub mod phy_frame;
\ No newline at end of file
+pub mod ofdm;
+pub mod phy_frame;
+pub mod preamble;
+pub mod preamble_detector;
+pub mod pulse_shaping;
+pub mod soft_decision;
+pub mod timing_recovery;
\ No newline at end of file
@@ -6,9 +6,18 @@ Input data
 -> Modulation
 -> Output Signal
 */
+use super::constellation::Constellation;
+use super::equalizer;
+use super::css::{self, CssConfig};
+use super::dsss::{self, PnCode};
+use super::frame_codec::FrameCodec;
+use super::fsk::{self, FskConfig};
+use super::ofdm::{OfdmConfig, OfdmModulator};
 use super::phy_frame;
+use super::preamble::Preamble;
+use super::pulse_shaping::PulseShape;
 use crate::asio_stream::{AudioTrack, OutputAudioStream};
-use crate::utils::{self, Byte};
+use crate::utils::{self, Bit, Byte};
 use cpal::traits::{DeviceTrait, HostTrait};
 use cpal::{SampleRate, SupportedStreamConfig};
 use futures::SinkExt;
@@ -16,31 +25,47 @@ use hound::{WavSpec, WavWriter};
 
 const SAMPLE_RATE: u32 = 48000;
 
-// If OFDM is enabled, the carrier_freq represents the redundant periods of the lowest frequency
+// Redundant periods of the lowest carrier for each bit, not used by OFDM
 pub const REDUNDANT_PERIODS: usize = 2;
 
+// the modulation of the data symbols, shared by `Modulator` and `Demodulation2`: both sides must
+// use the same one. OFDM is chosen by `enable_ofdm` of the constructors, the others by `set_modulation`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Modulation {
+    // PSK / QAM of `set_constellation` on the lowest carrier
+    Psk,
+    // PSK / QAM of `set_constellation` on the subcarriers of `carrier_freq_config`, see `OfdmConfig`
+    Ofdm,
+    // FSK on the tones of `carrier_freq_config`, see `FskConfig::new`
+    Fsk,
+    // chirp spread spectrum with the spreading factor, a long-range mode for very low SNR, see `CssConfig`
+    Css(usize),
+    // direct-sequence spread spectrum: each bit is spread by the PN code, each chip is a BPSK symbol
+    // of `redundant_periods` periods on the lowest carrier
+    Dsss(PnCode),
+}
+
 pub struct Modulator {
+    carrier_freq_config: Vec<u32>,
     carrier_freq: Vec<u32>,
     sample_rate: u32,
     redundant_periods: usize,
-    enable_ofdm: bool,
-    output_stream: OutputAudioStream<std::vec::IntoIter<f32>>,
-    config: SupportedStreamConfig,
+    constellation: Constellation,
+    modulation: Modulation,
+    pulse_shape: PulseShape,
+    // known symbols after the preamble for the equalizer of the demodulator
+    training_len: usize,
+    // Reed-Solomon code and size of the frames, None for the frames without encoding
+    frame_codec: Option<FrameCodec>,
+    // waveform of the preamble before each frame
+    preamble: Vec<f32>,
+    ofdm_modulator: Option<OfdmModulator>,
+    output_stream: Option<OutputAudioStream<std::vec::IntoIter<f32>>>,
+    config: Option<SupportedStreamConfig>,
 }
 
 impl Modulator {
     pub fn new(carrier_freq_config: Vec<u32>, sample_rate: u32, enable_ofdm: bool) -> Self {
-        let mut carrier_freq = vec![];
-        if enable_ofdm {
-            for i in 0..carrier_freq_config[2] {
-                carrier_freq.push(carrier_freq_config[0] + i * carrier_freq_config[1]);
-            }
-            // carrier_freq.push(6000);
-            // carrier_freq.push(12000);
-        } else {
-            carrier_freq.push(carrier_freq_config[0]);
-        }
-
         // let host = cpal::host_from_id(HostId::Asio).expect("failed to initialise ASIO host");
         let host = cpal::default_host();
         let device = host.default_output_device().unwrap();
@@ -56,16 +81,127 @@ impl Modulator {
 
         let output_stream = OutputAudioStream::new(&device, config.clone());
 
+        let mut modulator = Modulator::new_loopback(carrier_freq_config, sample_rate, enable_ofdm);
+        modulator.output_stream = Some(output_stream);
+        modulator.config = Some(config);
+        modulator
+    }
+
+    // A modulator without output device: only `bits_2_wave`, `modulate` and `send_bits_2_file` are available.
+    // The generated wave can be fed into `Demodulation2::new_loopback` directly.
+    pub fn new_loopback(carrier_freq_config: Vec<u32>, sample_rate: u32, enable_ofdm: bool) -> Self {
+        let mut carrier_freq = vec![];
+        if enable_ofdm {
+            for i in 0..carrier_freq_config[2] {
+                carrier_freq.push(carrier_freq_config[0] + i * carrier_freq_config[1]);
+            }
+            // carrier_freq.push(6000);
+            // carrier_freq.push(12000);
+        } else {
+            carrier_freq.push(carrier_freq_config[0]);
+        }
+
+        let ofdm_modulator = if enable_ofdm {
+            let ofdm_config = OfdmConfig::new(&carrier_freq_config, sample_rate)
+                .unwrap_or_else(|err| panic!("[Modulator] {:?}: {}", carrier_freq_config, err));
+            println!("[Modulator] ofdm config: {:?}", ofdm_config);
+            Some(OfdmModulator::new(ofdm_config))
+        } else {
+            None
+        };
+
         Modulator {
+            carrier_freq_config,
             carrier_freq,
             sample_rate,
             redundant_periods: REDUNDANT_PERIODS,
-            enable_ofdm,
-            output_stream,
-            config,
+            constellation: Constellation::Bpsk,
+            modulation: if enable_ofdm { Modulation::Ofdm } else { Modulation::Psk },
+            pulse_shape: PulseShape::Rectangular,
+            training_len: 0,
+            frame_codec: if enable_ofdm { Some(FrameCodec::default()) } else { None },
+            preamble: phy_frame::gen_preamble(sample_rate),
+            ofdm_modulator,
+            output_stream: None,
+            config: None,
+        }
+    }
+
+    pub fn set_redundant_periods(&mut self, redundant_periods: usize) {
+        self.redundant_periods = redundant_periods;
+    }
+
+    // BPSK by default, the demodulator must use the same constellation
+    pub fn set_constellation(&mut self, constellation: Constellation) {
+        self.constellation = constellation;
+        if let Some(ofdm_modulator) = self.ofdm_modulator.as_mut() {
+            ofdm_modulator.set_constellation(constellation);
+        }
+    }
+
+    // PSK by default (OFDM with `enable_ofdm`), the demodulator must use the same modulation
+    // OFDM cannot be switched on or off here, it is chosen by `new`.
+    pub fn set_modulation(&mut self, modulation: Modulation) {
+        assert_eq!(
+            modulation == Modulation::Ofdm,
+            self.modulation == Modulation::Ofdm,
+            "[Modulator] OFDM is chosen by `enable_ofdm` of `new`"
+        );
+        self.modulation = modulation;
+    }
+
+    // envelope of the PSK symbols (and the DSSS chips), or the edges of the OFDM symbols
+    // The demodulator must use the same pulse shape as the matched filter. FSK and CSS are
+    // phase continuous, they are not shaped.
+    pub fn set_pulse_shape(&mut self, pulse_shape: PulseShape) {
+        self.pulse_shape = pulse_shape;
+        if let Some(ofdm_modulator) = self.ofdm_modulator.as_mut() {
+            ofdm_modulator.set_pulse_shape(pulse_shape);
         }
     }
 
+    // training symbols sent after the preamble of each PSK / QAM frame (`equalizer::training_symbols`),
+    // 0 by default, the demodulator must enable the equalizer with the same `training_len`
+    pub fn set_training(&mut self, training_len: usize) {
+        self.training_len = training_len;
+    }
+
+    // Reed-Solomon code and size of the frames of every modulation, None to send the frames
+    // without encoding (`PHYFrame::new_no_encoding`). The default codec with OFDM, None with the
+    // others. The demodulator must use the same codec (`Demodulation2::set_frame_codec`).
+    pub fn set_frame_codec(&mut self, frame_codec: Option<FrameCodec>) {
+        self.frame_codec = frame_codec;
+    }
+
+    // the bits of data of each frame
+    fn frame_data_len(&self) -> usize {
+        match &self.frame_codec {
+            Some(frame_codec) => frame_codec.max_data_len(),
+            None => phy_frame::MAX_FRAME_DATA_LENGTH,
+        }
+    }
+
+    // the bits of the frame of `length` bits of `payload`, encoded by `frame_codec`
+    fn frame_bits(&self, length: usize, payload: Vec<Byte>) -> Vec<Bit> {
+        let frame_bits = match &self.frame_codec {
+            Some(frame_codec) => phy_frame::PHYFrame::new_with_codec(frame_codec, length, payload)
+                .unwrap()
+                .get_whole_frame_bits(),
+            None => phy_frame::PHYFrame::new_no_encoding(length, payload).1,
+        };
+        let decompressed_data = utils::read_compressed_u8_2_data(frame_bits);
+        println!(
+            "[bits_2_wave] decompressed_data.len(): {}",
+            decompressed_data.len()
+        );
+        decompressed_data
+    }
+
+    // the chirp of `phy_frame::gen_preamble` by default, the demodulator must use the same preamble
+    pub fn set_preamble(&mut self, preamble: &dyn Preamble) {
+        self.preamble = preamble.waveform(self.sample_rate);
+    }
+
     pub async fn test_carrier_wave(&mut self) {
         // use sin to generate a carrier wave
         let duration = 5.0; // seconds
@@ -85,8 +221,11 @@ impl Modulator {
 
         println!("[test_carrier_wave] wave length: {:?}", wave.len());
 
+        let config = self.config.clone().expect("[Modulator] no output device");
         self.output_stream
-            .send(AudioTrack::new(wave.into_iter(), self.config.clone()))
+            .as_mut()
+            .expect("[Modulator] no output device")
+            .send(AudioTrack::new(wave.into_iter(), config))
             .await
             .unwrap();
     }
@@ -94,37 +233,35 @@ impl Modulator {
     pub async fn bits_2_wave(&mut self, data: Vec<Byte>, len: isize) -> Vec<f32> {
         println!("[send_bits] send bits: {:?}", len);
 
-        // warm up
-        let mut modulated_signal: Vec<f32> = (0..16000)
-            .map(|x| (2.0 * std::f32::consts::PI * x as f32 / 48000.0 * 6000 as f32).sin())
-            .collect();
+        // warm up, only needed by a real output device
+        let mut modulated_signal: Vec<f32> = if self.output_stream.is_some() {
+            (0..16000)
+                .map(|x| (2.0 * std::f32::consts::PI * x as f32 / 48000.0 * 6000 as f32).sin())
+                .collect()
+        } else {
+            vec![]
+        };
 
         let mut len = len;
         let mut loop_cnt = 0;
 
-        if !self.enable_ofdm {
-            len -= phy_frame::MAX_FRAME_DATA_LENGTH as isize;
+        let frame_data_len = self.frame_data_len();
+
+        if self.modulation != Modulation::Ofdm {
+            len -= frame_data_len as isize;
 
             while len > 0 {
                 let mut payload = vec![];
-                for i in 0..(phy_frame::MAX_FRAME_DATA_LENGTH / 8) {
-                    payload.push(data[i + loop_cnt * (phy_frame::MAX_FRAME_DATA_LENGTH / 8)]);
+                for i in 0..(frame_data_len / 8) {
+                    payload.push(data[i + loop_cnt * (frame_data_len / 8)]);
                 }
                 println!("push in payload data: {:?}", payload);
-                println!("frame len: {}", phy_frame::MAX_FRAME_DATA_LENGTH);
-                let frame =
-                    phy_frame::PHYFrame::new_no_encoding(phy_frame::MAX_FRAME_DATA_LENGTH, payload);
-                let frame_bits = frame.1;
-                let decompressed_data = utils::read_compressed_u8_2_data(frame_bits);
-                println!(
-                    "[bits_2_wave] decompressed_data.len(): {}",
-                    decompressed_data.len()
-                );
+                println!("frame len: {}", frame_data_len);
+                let decompressed_data = self.frame_bits(frame_data_len, payload);
                 let modulated_psk_signal = self.modulate(&decompressed_data, 0);
 
                 // add FSK preamble
-                let preamble = phy_frame::gen_preamble(self.sample_rate);
-                modulated_signal.extend(preamble.clone());
+                modulated_signal.extend(self.preamble.iter());
                 modulated_signal.extend(modulated_psk_signal.clone());
 
                 println!(
@@ -132,7 +269,7 @@ impl Modulator {
                     modulated_signal.len()
                 );
 
-                len -= phy_frame::MAX_FRAME_DATA_LENGTH as isize;
+                len -= frame_data_len as isize;
                 loop_cnt += 1;
 
                 // wait for a while
@@ -141,26 +278,19 @@ impl Modulator {
             }
 
             // send the last frame
-            len += phy_frame::MAX_FRAME_DATA_LENGTH as isize;
+            len += frame_data_len as isize;
             println!("[bits_2_wave] remaining len: {:?}", len);
             let mut payload = vec![];
             for i in 0..((len + 7) / 8) {
-                payload.push(data[i as usize + loop_cnt * (phy_frame::MAX_FRAME_DATA_LENGTH / 8)]);
+                payload.push(data[i as usize + loop_cnt * (frame_data_len / 8)]);
             }
             println!("push in payload data: {:?}", payload);
             println!("frame len: {}", len);
-            let frame = phy_frame::PHYFrame::new_no_encoding(len as usize, payload);
-            let frame_bits = frame.1;
-            let decompressed_data = utils::read_compressed_u8_2_data(frame_bits);
-            println!(
-                "[bits_2_wave] decompressed_data.len(): {}",
-                decompressed_data.len()
-            );
+            let decompressed_data = self.frame_bits(len as usize, payload);
             let modulated_psk_signal = self.modulate(&decompressed_data, 0);
 
             // add FSK preamble
-            let preamble = phy_frame::gen_preamble(self.sample_rate);
-            modulated_signal.extend(preamble.clone());
+            modulated_signal.extend(self.preamble.iter());
             modulated_signal.extend(modulated_psk_signal.clone());
 
             println!(
@@ -169,67 +299,32 @@ impl Modulator {
             );
             println!("[bits_2_wave] send {} frames", loop_cnt + 1);
         } else {
-            // OFDM
+            // OFDM, a frame on each data subcarrier
             let carrier_cnt = self.carrier_freq.len();
-            len -= (phy_frame::MAX_FRAME_DATA_LENGTH * carrier_cnt) as isize;
+            len -= (frame_data_len * carrier_cnt) as isize;
             while len > 0 {
-                let mut modulated_psk_signal: Vec<f32> = vec![];
+                let mut frames_bits: Vec<Vec<Bit>> = vec![];
 
                 for i in 0..carrier_cnt {
                     let mut payload = vec![];
-                    for j in 0..(phy_frame::MAX_FRAME_DATA_LENGTH / 8) {
+                    for j in 0..(frame_data_len / 8) {
                         payload.push(
                             data[j
                                 + (loop_cnt * carrier_cnt + i)
-                                    * (phy_frame::MAX_FRAME_DATA_LENGTH / 8)],
+                                    * (frame_data_len / 8)],
                         );
                     }
                     println!("push in payload data: {:?}", payload);
-                    println!("frame len: {}", phy_frame::MAX_FRAME_DATA_LENGTH);
-                    let frame = phy_frame::PHYFrame::new(phy_frame::MAX_FRAME_DATA_LENGTH, payload);
-                    let frame_bits = frame.get_whole_frame_bits();
-                    let decompressed_data = utils::read_compressed_u8_2_data(frame_bits);
-                    println!(
-                        "[bits_2_wave ofdm] decompressed_data.len(): {}",
-                        decompressed_data.len()
-                    );
-                    let modulated_psk_signal_i = self.modulate(&decompressed_data, i);
-
-                    // nomalization - make the power of each carrier equal
-                    let modulated_psk_signal_i: Vec<f32> = modulated_psk_signal_i
-                        .iter()
-                        .map(|&x| {
-                            x / ((self.carrier_freq[i] as f32 / self.carrier_freq[0] as f32)
-                                .powf(2.0)) as f32
-                        })
-                        .collect();
-
-                    if i == 0 {
-                        modulated_psk_signal.extend(modulated_psk_signal_i.clone());
-                    } else {
-                        modulated_psk_signal = modulated_psk_signal
-                            .iter()
-                            .zip(modulated_psk_signal_i.iter())
-                            .map(|(a, b)| a + b)
-                            .collect();
-                    }
+                    println!("frame len: {}", frame_data_len);
+                    frames_bits.push(self.frame_bits(frame_data_len, payload));
                 }
 
-                // nomalization - make the maximum of the sequence equal to 1
-                let divisor = (1..(carrier_cnt + 1)).fold(0.0, |acc, x| {
-                    acc + 1.0
-                        / ((self.carrier_freq[x - 1] as f32 / self.carrier_freq[0] as f32)
-                            .powf(2.0)) as f32
-                });
-                modulated_psk_signal = modulated_psk_signal
-                    .iter()
-                    .map(|&x| x / divisor as f32)
-                    .collect();
+                // each frame is carried by a data subcarrier
+                let modulated_ofdm_signal = self.ofdm_modulator.as_ref().unwrap().modulate(&frames_bits);
 
                 // add FSK preamble
-                let preamble = phy_frame::gen_preamble(self.sample_rate);
-                modulated_signal.extend(preamble.clone());
-                modulated_signal.extend(modulated_psk_signal.clone());
+                modulated_signal.extend(self.preamble.iter());
+                modulated_signal.extend(modulated_ofdm_signal);
 
                 println!("[bits_2_wave ofdm] finish 1 ofdm frame");
                 println!(
@@ -237,7 +332,7 @@ impl Modulator {
                     modulated_signal.len()
                 );
 
-                len -= (phy_frame::MAX_FRAME_DATA_LENGTH * carrier_cnt) as isize;
+                len -= (frame_data_len * carrier_cnt) as isize;
                 loop_cnt += 1;
 
                 // wait for a while
@@ -246,14 +341,14 @@ impl Modulator {
             }
 
             // send the last frame
-            len += (phy_frame::MAX_FRAME_DATA_LENGTH * carrier_cnt) as isize;
+            len += (frame_data_len * carrier_cnt) as isize;
             println!("[bits_2_wave ofdm] remaining len: {:?}", len);
-            let mut modulated_psk_signal: Vec<f32> = vec![];
+            let mut frames_bits: Vec<Vec<Bit>> = vec![];
             let mut last_single_frames_cnt = 0;
             for i in 0..carrier_cnt {
                 let mut payload = vec![];
-                let bit_len = if len > phy_frame::MAX_FRAME_DATA_LENGTH as isize {
-                    phy_frame::MAX_FRAME_DATA_LENGTH
+                let bit_len = if len > frame_data_len as isize {
+                    frame_data_len
                 } else {
                     len as usize
                 };
@@ -263,61 +358,26 @@ impl Modulator {
                         payload.push(
                             data[j as usize
                                 + (loop_cnt * carrier_cnt + i)
-                                    * (phy_frame::MAX_FRAME_DATA_LENGTH / 8)],
+                                    * (frame_data_len / 8)],
                         );
                     }
                     last_single_frames_cnt += 1;
                 }
                 println!("push in payload data: {:?}", payload);
                 println!("frame len: {}", bit_len);
-                let frame = phy_frame::PHYFrame::new(bit_len, payload);
-                let frame_bits = frame.get_whole_frame_bits();
-                let decompressed_data = utils::read_compressed_u8_2_data(frame_bits);
-                println!(
-                    "[bits_2_wave ofdm] decompressed_data.len(): {}",
-                    decompressed_data.len()
-                );
-                let modulated_psk_signal_i = self.modulate(&decompressed_data, i);
-
-                // nomalization - make the power of each carrier equal
-                let modulated_psk_signal_i: Vec<f32> = modulated_psk_signal_i
-                    .iter()
-                    .map(|&x| {
-                        x / ((self.carrier_freq[i] as f32 / self.carrier_freq[0] as f32).powf(2.0))
-                            as f32
-                    })
-                    .collect();
-
-                if i == 0 {
-                    modulated_psk_signal.extend(modulated_psk_signal_i.clone());
-                } else {
-                    modulated_psk_signal = modulated_psk_signal
-                        .iter()
-                        .zip(modulated_psk_signal_i.iter())
-                        .map(|(a, b)| a + b)
-                        .collect();
-                }
-                len -= phy_frame::MAX_FRAME_DATA_LENGTH as isize;
+                frames_bits.push(self.frame_bits(bit_len, payload));
+                len -= frame_data_len as isize;
                 if len < 0 {
                     len = 0;
                 }
             }
 
-            // nomalization - make the maximum of the sequence equal to 1
-            let divisor = (1..(carrier_cnt + 1)).fold(0.0, |acc, x| {
-                acc + 1.0
-                    / ((self.carrier_freq[x - 1] as f32 / self.carrier_freq[0] as f32).powf(2.0))
-                        as f32
-            });
-            modulated_psk_signal = modulated_psk_signal
-                .iter()
-                .map(|&x| x / divisor as f32)
-                .collect();
+            // each frame is carried by a data subcarrier
+            let modulated_ofdm_signal = self.ofdm_modulator.as_ref().unwrap().modulate(&frames_bits);
 
             // add FSK preamble
-            let preamble = phy_frame::gen_preamble(self.sample_rate);
-            modulated_signal.extend(preamble.clone());
-            modulated_signal.extend(modulated_psk_signal.clone());
+            modulated_signal.extend(self.preamble.iter());
+            modulated_signal.extend(modulated_ofdm_signal);
 
             println!(
                 "[bits_2_wave ofdm] modulated_signal.len(): {}",
@@ -350,11 +410,11 @@ impl Modulator {
         // for debug
         output.push_back(modulated_signal.clone());
 
+        let config = self.config.clone().expect("[Modulator] no output device");
         self.output_stream
-            .send(AudioTrack::new(
-                modulated_signal.into_iter(),
-                self.config.clone(),
-            ))
+            .as_mut()
+            .expect("[Modulator] no output device")
+            .send(AudioTrack::new(modulated_signal.into_iter(), config))
             .await
             .unwrap();
 
@@ -398,36 +458,60 @@ impl Modulator {
     // translate the bits into modulated signal
     pub fn modulate(&self, bits: &Vec<u8>, carrrier_freq_id: usize) -> Vec<f32> {
         println!("[modulate] output: {:?}, length: {}", bits, bits.len());
+        match &self.modulation {
+            Modulation::Css(spreading_factor) => {
+                css::modulate(&CssConfig::new(self.sample_rate, *spreading_factor), bits)
+            }
+            Modulation::Fsk => {
+                let fsk_config = FskConfig::new(
+                    &self.carrier_freq_config,
+                    self.sample_rate,
+                    self.redundant_periods,
+                );
+                fsk::modulate(&fsk_config, bits)
+            }
+            Modulation::Dsss(pn_code) => {
+                let chips = dsss::spread(bits, pn_code);
+                self.modulate_psk(&chips, carrrier_freq_id, Constellation::Bpsk, 0)
+            }
+            Modulation::Psk | Modulation::Ofdm => {
+                self.modulate_psk(bits, carrrier_freq_id, self.constellation, self.training_len)
+            }
+        }
+    }
+
+    fn modulate_psk(
+        &self,
+        bits: &Vec<u8>,
+        carrrier_freq_id: usize,
+        constellation: Constellation,
+        training_len: usize,
+    ) -> Vec<f32> {
         let mut modulated_signal = vec![];
 
-        // redundant periods for each bit
-        let sample_cnt_each_bit =
+        // redundant periods for each symbol
+        let sample_cnt_each_symbol =
             self.sample_rate * self.redundant_periods as u32 / self.carrier_freq[0];
 
         println!(
-            "[modulate] frequence {}, with sample num {}",
-            self.carrier_freq[carrrier_freq_id], sample_cnt_each_bit
+            "[modulate] frequence {}, {:?}, with sample num {}",
+            self.carrier_freq[carrrier_freq_id], constellation, sample_cnt_each_symbol
         );
-        let mut bit_id = 0;
-        while bit_id < bits.len() {
-            let bit = bits[bit_id];
-            let freq = self.carrier_freq[carrrier_freq_id];
-            for i in 0..sample_cnt_each_bit {
-                let sample = (if bit == 0 {
-                    1.0
-                } else if bit == 1 {
-                    -1.0
-                } else {
-                    0.0
-                }) * (2.0
+        let freq = self.carrier_freq[carrrier_freq_id];
+        let envelope = self.pulse_shape.window(sample_cnt_each_symbol as usize);
+        let mut symbols = equalizer::training_symbols(training_len);
+        symbols.extend(constellation.map_bits(bits));
+        for (symbol_id, &(i_value, q_value)) in symbols.iter().enumerate() {
+            for i in 0..sample_cnt_each_symbol {
+                let phase = 2.0
                     * std::f64::consts::PI
                     * freq as f64
-                    * (i + bit_id as u32 * sample_cnt_each_bit) as f64
-                    / self.sample_rate as f64)
-                    .sin();
-                modulated_signal.push(sample as f32);
+                    * (i + symbol_id as u32 * sample_cnt_each_symbol) as f64
+                    / self.sample_rate as f64;
+                // I on the sine carrier, Q on the cosine carrier
+                let sample = i_value as f64 * phase.sin() + q_value as f64 * phase.cos();
+                modulated_signal.push(sample as f32 * envelope[i as usize]);
             }
-            bit_id += 1;
         }
 
         return modulated_signal;
@@ -0,0 +1,396 @@
+/*
+Bits of each data subcarrier
+-> IFFT (data + pilot subcarriers) + cyclic prefix
+-> raised-cosine edges inside the cyclic prefix (pulse shaping), overlapped with the neighbours
+-> OFDM symbols
+
+OFDM symbols
+-> remove cyclic prefix + FFT
+-> one-tap equalization (training symbol) + gain and phase correction, interpolated between the
+   pilots at both edges of the band
+-> Bits of each data subcarrier
+*/
+use super::constellation::{Constellation, DifferentialDetector};
+use super::demodulation::SymbolDemodulator;
+use super::link_metrics::SymbolDecision;
+use super::pulse_shaping::{self, PulseShape};
+use crate::utils::Bit;
+use anyhow::Error;
+use num_integer::Integer;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+// the length of the cyclic prefix is 1 / OFDM_CP_RATIO of the FFT size
+pub const OFDM_CP_RATIO: usize = 4;
+
+// value of the pilot subcarriers in every data symbol
+const PILOT_VALUE: Complex<f32> = Complex { re: 1.0, im: 0.0 };
+
+#[derive(Clone, Debug)]
+pub struct OfdmConfig {
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    pub cp_len: usize,
+    // FFT bins carrying data, one frame on each of them
+    pub data_bins: Vec<usize>,
+    // FFT bins carrying `PILOT_VALUE`, below the lowest data bin and above the highest one
+    pub pilot_bins: Vec<usize>,
+    // constellation of the data bins
+    pub constellation: Constellation,
+    // the edges of each symbol are `pulse_shape.edge_len(cp_len)` samples long
+    pub pulse_shape: PulseShape,
+}
+
+impl OfdmConfig {
+    // carrier_freq_config: [lowest carrier, interval, count], the same as `Modulator::new`
+    // The bin spacing is the greatest common divisor of the lowest carrier and the interval,
+    // so that every carrier falls on a FFT bin. A pilot is placed half an interval below the lowest
+    // carrier and another one half an interval above the highest carrier.
+    // Return an error if the carriers and the pilots do not fit on the FFT bins between 0 Hz and
+    // half the sample rate.
+    pub fn new(carrier_freq_config: &Vec<u32>, sample_rate: u32) -> Result<Self, Error> {
+        let (carrier_low, carrier_interval, carrier_cnt) = (
+            carrier_freq_config[0],
+            carrier_freq_config[1],
+            carrier_freq_config[2],
+        );
+        if carrier_cnt == 0 {
+            return Err(Error::msg("[OfdmConfig] no carriers"));
+        }
+        let bin_spacing = carrier_low.gcd(&carrier_interval);
+        if bin_spacing == 0 || sample_rate % bin_spacing != 0 {
+            return Err(Error::msg(format!(
+                "[OfdmConfig] the bin spacing {} Hz must divide the sample rate {} Hz",
+                bin_spacing, sample_rate
+            )));
+        }
+        let fft_size = (sample_rate / bin_spacing) as usize;
+        let low_bin = (carrier_low / bin_spacing) as usize;
+        let interval_bins = (carrier_interval / bin_spacing) as usize;
+
+        let pilot_offset = (interval_bins / 2).max(1);
+        if low_bin <= pilot_offset {
+            return Err(Error::msg(format!(
+                "[OfdmConfig] the lowest carrier {} Hz leaves no room for the pilot above 0 Hz",
+                carrier_low
+            )));
+        }
+
+        let data_bins: Vec<usize> = (0..carrier_cnt as usize)
+            .map(|i| low_bin + i * interval_bins)
+            .collect();
+        let pilot_bins = vec![
+            low_bin - pilot_offset,
+            data_bins.last().unwrap() + pilot_offset,
+        ];
+        if pilot_bins[1] >= fft_size / 2 {
+            return Err(Error::msg(format!(
+                "[OfdmConfig] the carriers and the pilot at {} Hz must be below half the sample rate",
+                pilot_bins[1] as u32 * bin_spacing
+            )));
+        }
+
+        Ok(OfdmConfig {
+            sample_rate,
+            fft_size,
+            cp_len: fft_size / OFDM_CP_RATIO,
+            data_bins,
+            pilot_bins,
+            constellation: Constellation::Bpsk,
+            pulse_shape: PulseShape::Rectangular,
+        })
+    }
+
+    pub fn symbol_len(&self) -> usize {
+        self.fft_size + self.cp_len
+    }
+
+    // The falling edge of a symbol is a cyclic suffix, overlapped with the rising edge at the
+    // beginning of the cyclic prefix of the next symbol. The FFT window stays untouched, but the
+    // echo tolerated by the cyclic prefix is shortened by the edge.
+    pub fn edge_len(&self) -> usize {
+        self.pulse_shape.edge_len(self.cp_len)
+    }
+
+    pub fn bin_freq(&self, bin: usize) -> f32 {
+        bin as f32 * self.sample_rate as f32 / self.fft_size as f32
+    }
+
+    // data and pilot bins
+    fn used_bins(&self) -> Vec<usize> {
+        let mut bins = self.data_bins.clone();
+        bins.extend(self.pilot_bins.iter());
+        bins
+    }
+}
+
+// known BPSK value of each bin in the training symbol
+fn training_value(bin: usize) -> Complex<f32> {
+    if bin % 2 == 0 {
+        Complex::new(1.0, 0.0)
+    } else {
+        Complex::new(-1.0, 0.0)
+    }
+}
+
+pub struct OfdmModulator {
+    config: OfdmConfig,
+    ifft: Arc<dyn Fft<f32>>,
+}
+
+impl OfdmModulator {
+    pub fn new(config: OfdmConfig) -> Self {
+        let ifft = FftPlanner::new().plan_fft_inverse(config.fft_size);
+        OfdmModulator { config, ifft }
+    }
+
+    pub fn config(&self) -> &OfdmConfig {
+        &self.config
+    }
+
+    pub fn set_constellation(&mut self, constellation: Constellation) {
+        self.config.constellation = constellation;
+    }
+
+    pub fn set_pulse_shape(&mut self, pulse_shape: PulseShape) {
+        self.config.pulse_shape = pulse_shape;
+    }
+
+    // [training symbol][data symbol 0]...[data symbol n-1], each of them begins with the cyclic prefix
+    // @param bits: bits[i] is transmitted on the i-th data bin, all of them have the same length
+    pub fn modulate(&self, bits: &Vec<Vec<Bit>>) -> Vec<f32> {
+        assert_eq!(bits.len(), self.config.data_bins.len());
+        let points: Vec<Vec<(f32, f32)>> = bits
+            .iter()
+            .map(|bits| self.config.constellation.map_bits(bits))
+            .collect();
+        let symbol_cnt = points[0].len();
+
+        let training: Vec<(usize, Complex<f32>)> = self
+            .config
+            .used_bins()
+            .iter()
+            .map(|&bin| (bin, training_value(bin)))
+            .collect();
+        let mut modulated_signal = vec![];
+        self.overlap_symbol(&mut modulated_signal, self.gen_symbol(&training));
+
+        for symbol_id in 0..symbol_cnt {
+            let mut values = vec![];
+            for (i, &bin) in self.config.data_bins.iter().enumerate() {
+                let (i_value, q_value) = points[i][symbol_id];
+                values.push((bin, Complex::new(i_value, q_value)));
+            }
+            for &bin in &self.config.pilot_bins {
+                values.push((bin, PILOT_VALUE));
+            }
+            self.overlap_symbol(&mut modulated_signal, self.gen_symbol(&values));
+        }
+
+        // nomalization - make the maximum of the sequence equal to 1
+        let max = modulated_signal
+            .iter()
+            .fold(0.0_f32, |acc, &x| acc.max(x.abs()));
+        if max > 0.0 {
+            modulated_signal = modulated_signal.iter().map(|&x| x / max).collect();
+        }
+
+        println!(
+            "[ofdm modulate] {} symbols, fft size {}, cp {}, length {}",
+            symbol_cnt + 1,
+            self.config.fft_size,
+            self.config.cp_len,
+            modulated_signal.len()
+        );
+
+        return modulated_signal;
+    }
+
+    // append the symbol, its rising edge is added onto the falling edge of the previous symbol
+    fn overlap_symbol(&self, modulated_signal: &mut Vec<f32>, symbol: Vec<f32>) {
+        let overlap_len = self.config.edge_len().min(modulated_signal.len());
+        let overlap_start = modulated_signal.len() - overlap_len;
+        for i in 0..overlap_len {
+            modulated_signal[overlap_start + i] += symbol[i];
+        }
+        modulated_signal.extend(symbol[overlap_len..].iter());
+    }
+
+    // IFFT of the given bins with Hermitian symmetry (real output), with cyclic prefix,
+    // and with the cyclic suffix of the falling edge if the pulse shaping is enabled
+    fn gen_symbol(&self, values: &Vec<(usize, Complex<f32>)>) -> Vec<f32> {
+        let fft_size = self.config.fft_size;
+        let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+        for &(bin, value) in values {
+            buffer[bin] = value;
+            buffer[fft_size - bin] = value.conj();
+        }
+        self.ifft.process(&mut buffer);
+
+        let body: Vec<f32> = buffer.iter().map(|x| x.re).collect();
+        let mut symbol = body[fft_size - self.config.cp_len..].to_vec();
+        symbol.extend(body.iter());
+
+        let edge_len = self.config.edge_len();
+        if edge_len > 0 {
+            symbol.extend(body[0..edge_len].iter());
+            let ramp = pulse_shaping::rising_edge(edge_len);
+            let len = symbol.len();
+            for i in 0..edge_len {
+                symbol[i] *= ramp[i];
+                symbol[len - 1 - i] *= ramp[i];
+            }
+        }
+        symbol
+    }
+}
+
+pub struct OfdmDemodulator {
+    config: OfdmConfig,
+    fft: Arc<dyn Fft<f32>>,
+    // channel response of the data bins and the pilot bins, estimated from the training symbol
+    channel: Option<Vec<Complex<f32>>>,
+    // one for each data bin, only used by differential constellations
+    differential_detectors: Vec<DifferentialDetector>,
+    decisions: Vec<Option<SymbolDecision>>,
+}
+
+impl OfdmDemodulator {
+    pub fn new(config: OfdmConfig) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(config.fft_size);
+        let differential_detectors = vec![DifferentialDetector::default(); config.data_bins.len()];
+        OfdmDemodulator {
+            config,
+            fft,
+            channel: None,
+            differential_detectors,
+            decisions: vec![],
+        }
+    }
+
+    // the channel response of each data bin, after the training symbol is received
+    pub fn channel(&self) -> Option<Vec<Complex<f32>>> {
+        self.channel
+            .as_ref()
+            .map(|channel| channel[0..self.config.data_bins.len()].to_vec())
+    }
+
+    // FFT of the symbol without the cyclic prefix, return the values of the used bins
+    fn fft_symbol(&self, window: &[f32]) -> Vec<Complex<f32>> {
+        let mut buffer: Vec<Complex<f32>> = window[self.config.cp_len..]
+            .iter()
+            .map(|&x| Complex::new(x, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+        self.config
+            .used_bins()
+            .iter()
+            .map(|&bin| buffer[bin])
+            .collect()
+    }
+}
+
+impl SymbolDemodulator for OfdmDemodulator {
+    fn carrier_cnt(&self) -> usize {
+        self.config.data_bins.len()
+    }
+
+    fn symbol_len(&self) -> usize {
+        self.config.symbol_len()
+    }
+
+    // the training symbol is used instead of the gain
+    fn reset(&mut self, _gain: f32) {
+        self.channel = None;
+        for detector in self.differential_detectors.iter_mut() {
+            detector.reset();
+        }
+    }
+
+    fn demodulate(&mut self, window: &[f32]) -> Vec<Vec<Bit>> {
+        let values = self.fft_symbol(window);
+        let data_cnt = self.config.data_bins.len();
+        self.decisions = vec![None; data_cnt];
+
+        let channel = match &self.channel {
+            Some(channel) => channel,
+            None => {
+                // training symbol: one-tap channel estimation of each bin
+                let channel = self
+                    .config
+                    .used_bins()
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(&bin, &value)| value / training_value(bin))
+                    .collect();
+                self.channel = Some(channel);
+                return vec![vec![]; data_cnt];
+            }
+        };
+
+        // change of the channel since the training symbol at the pilots, e.g. the phase drift of a
+        // frequency offset or the gain of a fading, interpolated linearly between them
+        let pilot_changes: Vec<Complex<f32>> = values[data_cnt..]
+            .iter()
+            .zip(channel[data_cnt..].iter())
+            .map(|(&value, &h)| value / (h * PILOT_VALUE))
+            .collect();
+        let (low_pilot, high_pilot) = (self.config.pilot_bins[0], self.config.pilot_bins[1]);
+        let changes: Vec<Complex<f32>> = self
+            .config
+            .data_bins
+            .iter()
+            .map(|&bin| {
+                let weight = (bin - low_pilot) as f32 / (high_pilot - low_pilot) as f32;
+                let change = pilot_changes[0] * (1.0 - weight) + pilot_changes[1] * weight;
+                if change.norm() > 0.0 {
+                    change
+                } else {
+                    Complex::new(1.0, 0.0)
+                }
+            })
+            .collect();
+
+        let constellation = self.config.constellation;
+        let half_distance = constellation.half_distance();
+        let equalized: Vec<Complex<f32>> = (0..data_cnt)
+            .map(|i| values[i] / (channel[i] * changes[i]))
+            .collect();
+        if constellation.is_differential() {
+            let mut bits = vec![];
+            for (i, value) in equalized.iter().enumerate() {
+                let detector = &mut self.differential_detectors[i];
+                bits.push(detector.detect(constellation, value.re, value.im));
+                self.decisions[i] = detector.change().map(|change| SymbolDecision {
+                    received: change,
+                    decided: constellation.decide(change.0, change.1),
+                    half_distance,
+                    phase_error: None,
+                    constellation,
+                });
+            }
+            return bits;
+        }
+        // the pilots correct the phase error of each bin
+        equalized
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let bits = constellation.demap(value.re, value.im);
+                self.decisions[i] = Some(SymbolDecision {
+                    received: (value.re, value.im),
+                    decided: constellation.map(&bits),
+                    half_distance,
+                    phase_error: Some(changes[i].arg()),
+                    constellation,
+                });
+                bits
+            })
+            .collect()
+    }
+
+    fn decisions(&self) -> Vec<Option<SymbolDecision>> {
+        self.decisions.clone()
+    }
+}
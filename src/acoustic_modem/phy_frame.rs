@@ -1,14 +1,180 @@
 use std::vec;
 
+use super::crc;
+use super::frame_codec::{self, FrameCodec};
+use super::preamble::{ChirpPreamble, Preamble};
 use crate::utils::{self, Bit, Byte};
 use anyhow::{Error, Result};
 use code_rs::bits::Hexbit;
-use code_rs::coding::reed_solomon;
 
+// the data and the encoded bits of the frames of the default `FrameCodec`
 pub const MAX_FRAME_DATA_LENGTH: usize = 72;
 pub const FRAME_PAYLOAD_LENGTH: usize = 144;
-pub const FRAME_LENGTH_LENGTH: usize = 12;
+pub const FRAME_LENGTH_LENGTH: usize = 8;
 pub const FRAME_LENGTH_LENGTH_NO_ENCODING: usize = 16;
+// CRC of the frames of at most `MAX_FRAME_DATA_LENGTH` bits, the longer ones carry a CRC-32
+pub const FRAME_CRC_LENGTH: usize = 16;
+// the bits of a frame without encoding, i.e. the `data_len` of `Demodulation2::listening`
+pub const FRAME_LENGTH_NO_ENCODING: usize =
+    FRAME_LENGTH_LENGTH_NO_ENCODING + MAX_FRAME_DATA_LENGTH + FRAME_CRC_LENGTH;
+
+// the result of the decoding of a frame, see `link_metrics::CarrierMetrics`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FrameStatus {
+    Ok,
+    // the frame is decoded, but the CRC does not match: the bits are corrupted, the frame must be
+    // sent again
+    CrcMismatch,
+    // too many errors for Reed-Solomon, or a wrong length
+    #[default]
+    Undecodable,
+}
+
+impl FrameStatus {
+    // the status of the error of `decode`, `Undecodable` if it is not a CRC mismatch
+    pub fn of_error(error: &Error) -> Self {
+        error
+            .downcast_ref::<FrameStatus>()
+            .copied()
+            .unwrap_or(FrameStatus::Undecodable)
+    }
+}
+
+impl std::fmt::Display for FrameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for FrameStatus {}
+
+// CRC-16 for the frames of at most `MAX_FRAME_DATA_LENGTH` bits, CRC-32 for the longer ones
+pub fn crc_length(length: usize) -> usize {
+    if length > MAX_FRAME_DATA_LENGTH {
+        32
+    } else {
+        FRAME_CRC_LENGTH
+    }
+}
+
+// the CRC of the length and the data of a frame, `crc_length` bits
+pub fn frame_crc(bits: &[Bit], crc_length: usize) -> Vec<Bit> {
+    if crc_length == 32 {
+        crc::to_bits(crc::crc32(bits), 32)
+    } else {
+        crc::to_bits(crc::crc16(bits) as u32, 16)
+    }
+}
+
+// Err(FrameStatus::CrcMismatch) if `crc` is not the CRC of `bits`
+pub fn check_crc(bits: &[Bit], crc: &[Bit]) -> Result<(), Error> {
+    if frame_crc(bits, crc.len()) != crc {
+        return Err(Error::new(FrameStatus::CrcMismatch));
+    }
+    Ok(())
+}
+
+// version of the frame header, a receiver drops the headers of another version
+pub const HEADER_VERSION: u8 = 1;
+// version: <2 bits>, type: <2 bits>, flags: <4 bits>, sequence: <8 bits>, source: <8 bits>,
+// destination: <8 bits>
+pub const FRAME_HEADER_LENGTH: usize = 32;
+// the destination of the frames for every node, e.g. the beacons
+pub const BROADCAST_ADDRESS: u8 = 0xFF;
+
+// flags of the frame header
+pub const FLAG_ACK_REQUEST: u8 = 0b0001;
+pub const FLAG_MORE_FRAGMENTS: u8 = 0b0010;
+pub const FLAG_RETRANSMISSION: u8 = 0b0100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameType {
+    Data,
+    Ack,
+    Nack,
+    Beacon,
+}
+
+impl FrameType {
+    fn code(self) -> u8 {
+        match self {
+            FrameType::Data => 0,
+            FrameType::Ack => 1,
+            FrameType::Nack => 2,
+            FrameType::Beacon => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code & 0b11 {
+            0 => FrameType::Data,
+            1 => FrameType::Ack,
+            2 => FrameType::Nack,
+            _ => FrameType::Beacon,
+        }
+    }
+}
+
+// the header at the beginning of the data of a frame, for the MAC layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameHeader {
+    pub frame_type: FrameType,
+    // sequence number of the frame, wraps around after 255
+    pub seq: u8,
+    // node IDs, `BROADCAST_ADDRESS` as the destination for every node
+    pub src: u8,
+    pub dst: u8,
+    // `FLAG_*`, 4 bits
+    pub flags: u8,
+}
+
+impl FrameHeader {
+    pub fn new(frame_type: FrameType, seq: u8, src: u8, dst: u8) -> Self {
+        FrameHeader {
+            frame_type,
+            seq,
+            src,
+            dst,
+            flags: 0,
+        }
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    // whether the node `address` should take the frame
+    pub fn is_for(&self, address: u8) -> bool {
+        self.dst == address || self.dst == BROADCAST_ADDRESS
+    }
+
+    pub fn to_bytes(&self) -> Vec<Byte> {
+        vec![
+            HEADER_VERSION << 6 | self.frame_type.code() << 4 | self.flags & 0b1111,
+            self.seq,
+            self.src,
+            self.dst,
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[Byte]) -> Result<Self, Error> {
+        if bytes.len() < FRAME_HEADER_LENGTH / 8 {
+            return Err(Error::msg("Frame too short for the header"));
+        }
+        let version = bytes[0] >> 6;
+        if version != HEADER_VERSION {
+            let err_msg = format!("Unknown frame header version: {}", version);
+            return Err(Error::msg(err_msg));
+        }
+        Ok(FrameHeader {
+            frame_type: FrameType::from_code(bytes[0] >> 4),
+            seq: bytes[1],
+            src: bytes[2],
+            dst: bytes[3],
+            flags: bytes[0] & 0b1111,
+        })
+    }
+}
 
 pub struct PHYFrame {
     length: usize,
@@ -16,28 +182,70 @@ pub struct PHYFrame {
 }
 
 impl PHYFrame {
-    // Preamble: 01010101
-    // Length: <2 Hexbits>
-    // Data: <12 Hexbits>
-    // Preserved for future use: <2 Hexbits>
+    // Preamble: the waveform of `preamble::Preamble`, sent by the modulator
+    // Length: <8 bits>
+    // Data: <72 bits = 12 Hexbits>
+    // CRC-16 of the length and the data: <16 bits>
     // Parity: <8 Hexbits>
     // Payload Total: <24 Hexbits>
-    pub fn new(length: usize, data: Vec<Byte>) -> Self {
-        let payload = PHYFrame::data_2_payload(data, length).unwrap();
-        PHYFrame { length, payload }
+    // Err if the data is longer than MAX_FRAME_DATA_LENGTH bits
+    pub fn new(length: usize, data: Vec<Byte>) -> Result<Self, Error> {
+        PHYFrame::new_with_codec(&FrameCodec::default(), length, data)
+    }
+
+    // the frame of any Reed-Solomon code and size, see `frame_codec::FrameCodec`
+    // Err if the data is longer than `codec.max_data_len()` bits
+    pub fn new_with_codec(
+        codec: &FrameCodec,
+        length: usize,
+        data: Vec<Byte>,
+    ) -> Result<Self, Error> {
+        let payload = frame_codec::bits_2_hexbits(&codec.encode(length, data)?);
+        Ok(PHYFrame { length, payload })
     }
 
-    // Just merge length and data into payload: Vec<Byte>, no encoding, no hexbit
+    // Just merge length, data and CRC into payload: Vec<Byte>, no encoding, no hexbit
+    // Length: <16 bits>
+    // Data: <length bits>
+    // CRC of the length and the data: <16 bits>, <32 bits> if longer than MAX_FRAME_DATA_LENGTH
+    // padded to FRAME_LENGTH_NO_ENCODING bits
     pub fn new_no_encoding(length: usize, data: Vec<Byte>) -> (usize, Vec<Byte>) {
-        let mut payload: Vec<Byte> = Vec::new();
-        payload.push((length >> 8) as u8);
-        payload.push((length & 0xff) as u8);
-        payload.extend(data);
-        while payload.len() < (MAX_FRAME_DATA_LENGTH + FRAME_LENGTH_LENGTH_NO_ENCODING) / 8 {
-            payload.push(0);
+        let mut bits = crc::to_bits(length as u32, FRAME_LENGTH_LENGTH_NO_ENCODING);
+        let mut data = utils::read_compressed_u8_2_data(data);
+        data.resize(length, 0);
+        bits.extend(data);
+        bits.extend(frame_crc(&bits, crc_length(length)));
+        if bits.len() < FRAME_LENGTH_NO_ENCODING {
+            bits.resize(FRAME_LENGTH_NO_ENCODING, 0);
         }
 
-        (length, payload)
+        (length, utils::read_data_2_compressed_u8(bits))
+    }
+
+    // put the header before the data of `length` bits, return the length and the data to be
+    // passed to `new` or `new_no_encoding`, the header takes FRAME_HEADER_LENGTH bits of the
+    // frame, so `new` takes at most MAX_FRAME_DATA_LENGTH - FRAME_HEADER_LENGTH bits of data
+    // Header: <FRAME_HEADER_LENGTH bits>
+    // Data: <length bits>
+    pub fn serialize_header(
+        header: &FrameHeader,
+        length: usize,
+        data: Vec<Byte>,
+    ) -> (usize, Vec<Byte>) {
+        let mut bytes = header.to_bytes();
+        bytes.extend(data.into_iter().take(length.div_ceil(8)));
+        (FRAME_HEADER_LENGTH + length, bytes)
+    }
+
+    // the header and the data bits of a frame built by `serialize_header`, from the bits returned
+    // by `FrameCodec::decode` or `demodulation::decode_no_encoding`
+    pub fn parse_header(bits: &[Bit]) -> Result<(FrameHeader, Vec<Bit>), Error> {
+        if bits.len() < FRAME_HEADER_LENGTH {
+            return Err(Error::msg("Frame too short for the header"));
+        }
+        let bytes = utils::read_data_2_compressed_u8(bits[..FRAME_HEADER_LENGTH].to_vec());
+        let header = FrameHeader::from_bytes(&bytes)?;
+        Ok((header, bits[FRAME_HEADER_LENGTH..].to_vec()))
     }
 
     pub fn get_whole_frame_bits(&self) -> Vec<Bit> {
@@ -46,40 +254,10 @@ impl PHYFrame {
     }
 
     // the length of data must be less than or equal to MAX_FRAME_DATA_LENGTH bits.
-    // length and data are encoded into payload
+    // length and data are encoded into payload by the default `FrameCodec`
     pub fn data_2_payload(data: Vec<u8>, len: usize) -> Result<Vec<Hexbit>, Error> {
-        if len > MAX_FRAME_DATA_LENGTH || data.len() * 8 > MAX_FRAME_DATA_LENGTH {
-            let err_msg = format!(
-                "Data length exceeds maximum frame data length: {}",
-                MAX_FRAME_DATA_LENGTH
-            );
-            return Err(Error::msg(err_msg));
-        }
-
-        // add length info into data
-        let mut hexbits_data = usize_length_2_hexbits_length(len);
-
-        // extend the length of `data: Vec<u8>` to 96 bits, tranverse into `Vec<Hexbit>`
-        let mut data = data;
-        let mut data_len = data.len();
-        while data_len < MAX_FRAME_DATA_LENGTH / 8 {
-            data.push(0);
-            data_len += 1;
-        }
-        hexbits_data.extend(utils::u8_2_code_rs_hexbit(data));
-
-        // extend the length of `hexbits_data: Vec<Hexbit>` to 216 bits
-        let mut hexbits_data_len = hexbits_data.len();
-        while hexbits_data_len < FRAME_PAYLOAD_LENGTH / 6 {
-            hexbits_data.push(Hexbit::new(0));
-            hexbits_data_len += 1;
-        }
-        let data = hexbits_data;
-
-        // RS encoding
-        let mut array_data: [Hexbit; 24] = data.try_into().unwrap();
-        reed_solomon::short::encode(&mut array_data);
-        let payload = array_data.to_vec();
+        let bits = FrameCodec::default().encode(len, data)?;
+        let payload = frame_codec::bits_2_hexbits(&bits);
 
         println!(
             "[data_2_payload] payload: {:?}, length: {}",
@@ -91,25 +269,13 @@ impl PHYFrame {
     }
 
     // reconstruct & get back the data
-    pub fn payload_2_data(payload: Vec<Hexbit>) -> Result<(Vec<Byte>, usize), Error> {
-        // RS decoding
-        let mut array_payload: [Hexbit; 24] = payload.try_into().unwrap();
-        reed_solomon::short::decode(&mut array_payload);
-        let payload = array_payload.to_vec();
+    // return the data, its length and the hexbits corrected by RS
+    pub fn payload_2_data(payload: Vec<Hexbit>) -> Result<(Vec<Byte>, usize, usize), Error> {
+        let bits = frame_codec::hexbits_2_bits(&payload);
+        let (data, corrections) = FrameCodec::default().decode_hard(bits)?;
+        let length = data.len();
 
-        // println!(
-        //     "[payload_2_data] payload: {:?}, length: {}",
-        //     payload,
-        //     payload.len()
-        // );
-
-        // get the length
-        let length = hexbits_length_2_usize_length(payload[0..2].to_vec());
-
-        // get the data
-        let data = utils::code_rs_hexbit_2_u8(payload[2..22].to_vec());
-
-        return Ok((data, length));
+        return Ok((utils::read_data_2_compressed_u8(data), length, corrections));
     }
 
     pub fn construct_payload_format(input: Vec<u8>) -> Vec<Vec<u8>> {
@@ -163,6 +329,10 @@ impl SimpleFrame {
         }
     }
 
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
     pub fn into_audio(&self, redundent_times: usize, padding_len: usize) -> Vec<f32> {
         let mut redundent = 1;
         if redundent_times > 1 {
@@ -192,57 +362,7 @@ impl SimpleFrame {
     }
 }
 
+// the default preamble of the frames, see `preamble::ChirpPreamble`
 pub fn gen_preamble(sample_rate: u32) -> Vec<f32> {
-    let start = 8e2;
-    let end = 8e3;
-    let half_length = 350;
-    let dx: f64 = 1.0 / sample_rate as f64;
-    let step = (end - start) as f64 / half_length as f64;
-    let mut fp: Vec<f64> = (0..half_length).map(|i| start + i as f64 * step).collect();
-    let fp_rev = fp.clone().into_iter().rev();
-    fp.pop();
-    fp.extend(fp_rev);
-
-    let mut res = vec![];
-
-    res.push(0.0);
-    for i in 1..fp.len() {
-        let trap_area = (fp[i] + fp[i - 1]) * dx / 2.0;
-        res.push(res[i - 1] + trap_area);
-    }
-
-    res.extend(vec![0.0; 10].iter());
-
-    res.into_iter()
-        .map(|x| (2.0 * std::f64::consts::PI * x).sin() as f32)
-        .collect()
-}
-
-pub fn usize_length_2_hexbits_length(length: usize) -> Vec<Hexbit> {
-    // FRAME_LEGNTH_LENGTH must be 12
-    assert_eq!(FRAME_LENGTH_LENGTH, 12);
-
-    // length must less than 2^12
-    assert!(length < 4096);
-
-    let hexbits_length = vec![
-        Hexbit::new((length >> 6) as u8),
-        Hexbit::new((length & 0b111111) as u8),
-    ];
-    return hexbits_length;
-}
-
-pub fn hexbits_length_2_usize_length(length: Vec<Hexbit>) -> usize {
-    // FRAME_LEGNTH_LENGTH must be 12
-    assert_eq!(FRAME_LENGTH_LENGTH, 12);
-
-    // length.len must be 2
-    assert_eq!(length.len(), 2);
-
-    let mut len = 0;
-    len |= length[0].bits() as usize;
-    len <<= 6;
-    len |= length[1].bits() as usize;
-
-    return len;
+    ChirpPreamble::default().waveform(sample_rate)
 }
@@ -0,0 +1,173 @@
+/*
+Preamble families
+
+Preamble (`Modulator::set_preamble`)
+-> waveform at the sample rate, sent before each frame
+
+Received Signal (`Demodulation2::set_preamble`, the same preamble)
+-> normalized cross-correlation with the waveform (`PreambleDetector`)
+-> start of the frame
+
+A good preamble has a sharp autocorrelation peak and low sidelobes, and lies in the band of the
+speaker and the microphone. The chirp sweeps the whole band, the PN codes and the Zadoff-Chu
+sequence are sent around a carrier, with a bandwidth of about `sample_rate / chip_len`.
+*/
+use super::dsss::PnCode;
+use num_integer::Integer;
+
+pub trait Preamble: std::fmt::Debug + Send + Sync {
+    // the waveform of the preamble at `sample_rate`, with a peak amplitude of 1
+    fn waveform(&self, sample_rate: u32) -> Vec<f32>;
+
+    // the lowest and the highest frequency of the preamble in Hz
+    fn band(&self, sample_rate: u32) -> (f32, f32);
+}
+
+// up chirp from `start` to `end` Hz in `half_len` samples, then down chirp back to `start`
+// The default is the preamble of `phy_frame::gen_preamble`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChirpPreamble {
+    pub start: f32,
+    pub end: f32,
+    pub half_len: usize,
+}
+
+impl Default for ChirpPreamble {
+    fn default() -> Self {
+        ChirpPreamble {
+            start: 8e2,
+            end: 8e3,
+            half_len: 350,
+        }
+    }
+}
+
+impl Preamble for ChirpPreamble {
+    fn waveform(&self, sample_rate: u32) -> Vec<f32> {
+        let dx: f64 = 1.0 / sample_rate as f64;
+        let step = (self.end - self.start) as f64 / self.half_len as f64;
+        let mut fp: Vec<f64> = (0..self.half_len)
+            .map(|i| self.start as f64 + i as f64 * step)
+            .collect();
+        let fp_rev = fp.clone().into_iter().rev();
+        fp.pop();
+        fp.extend(fp_rev);
+
+        // phase in cycles, integral of the frequency by the trapezoidal rule
+        let mut res = vec![];
+        res.push(0.0);
+        for i in 1..fp.len() {
+            let trap_area = (fp[i] + fp[i - 1]) * dx / 2.0;
+            res.push(res[i - 1] + trap_area);
+        }
+
+        res.extend(vec![0.0; 10].iter());
+
+        res.into_iter()
+            .map(|x| (2.0 * std::f64::consts::PI * x).sin() as f32)
+            .collect()
+    }
+
+    fn band(&self, _sample_rate: u32) -> (f32, f32) {
+        (self.start.min(self.end), self.start.max(self.end))
+    }
+}
+
+// Barker code of the length (2, 3, 4, 5, 7, 11, 13), BPSK on the carrier, `chip_len` samples per chip
+#[derive(Clone, Debug, PartialEq)]
+pub struct BarkerPreamble {
+    pub len: usize,
+    pub carrier: f32,
+    pub chip_len: usize,
+}
+
+impl Preamble for BarkerPreamble {
+    fn waveform(&self, sample_rate: u32) -> Vec<f32> {
+        bpsk_chips(
+            &PnCode::Barker(self.len),
+            self.carrier,
+            self.chip_len,
+            sample_rate,
+        )
+    }
+
+    fn band(&self, sample_rate: u32) -> (f32, f32) {
+        chip_band(self.carrier, self.chip_len, sample_rate)
+    }
+}
+
+// m-sequence of the LFSR degree (3..=10), BPSK on the carrier, `chip_len` samples per chip
+#[derive(Clone, Debug, PartialEq)]
+pub struct MSequencePreamble {
+    pub degree: usize,
+    pub carrier: f32,
+    pub chip_len: usize,
+}
+
+impl Preamble for MSequencePreamble {
+    fn waveform(&self, sample_rate: u32) -> Vec<f32> {
+        bpsk_chips(
+            &PnCode::MSequence(self.degree),
+            self.carrier,
+            self.chip_len,
+            sample_rate,
+        )
+    }
+
+    fn band(&self, sample_rate: u32) -> (f32, f32) {
+        chip_band(self.carrier, self.chip_len, sample_rate)
+    }
+}
+
+// Zadoff-Chu sequence of the root and the odd length (coprime), the phase of each chip modulates
+// the carrier, `chip_len` samples per chip
+// Its periodic autocorrelation is 0 out of the peak, and its amplitude is constant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZadoffChuPreamble {
+    pub root: usize,
+    pub len: usize,
+    pub carrier: f32,
+    pub chip_len: usize,
+}
+
+impl Preamble for ZadoffChuPreamble {
+    fn waveform(&self, sample_rate: u32) -> Vec<f32> {
+        assert!(
+            self.len % 2 == 1 && self.root.gcd(&self.len) == 1,
+            "[ZadoffChuPreamble] the length must be odd and coprime to the root"
+        );
+        let omega = 2.0 * std::f64::consts::PI * self.carrier as f64 / sample_rate as f64;
+        (0..self.len * self.chip_len)
+            .map(|t| {
+                let n = (t / self.chip_len) as f64;
+                let phase =
+                    -std::f64::consts::PI * self.root as f64 * n * (n + 1.0) / self.len as f64;
+                (omega * t as f64 + phase).cos() as f32
+            })
+            .collect()
+    }
+
+    fn band(&self, sample_rate: u32) -> (f32, f32) {
+        chip_band(self.carrier, self.chip_len, sample_rate)
+    }
+}
+
+// chip 0 -> +sin, chip 1 -> -sin
+fn bpsk_chips(pn_code: &PnCode, carrier: f32, chip_len: usize, sample_rate: u32) -> Vec<f32> {
+    let omega = 2.0 * std::f32::consts::PI * carrier / sample_rate as f32;
+    pn_code
+        .chips()
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &chip)| {
+            let sign = if chip == 0 { 1.0 } else { -1.0 };
+            (i * chip_len..(i + 1) * chip_len).map(move |t| sign * (omega * t as f32).sin())
+        })
+        .collect()
+}
+
+// the main lobe of the spectrum of the chips around the carrier
+fn chip_band(carrier: f32, chip_len: usize, sample_rate: u32) -> (f32, f32) {
+    let chip_rate = sample_rate as f32 / chip_len as f32;
+    ((carrier - chip_rate).max(0.0), carrier + chip_rate)
+}
@@ -0,0 +1,180 @@
+/*
+Preamble detection by normalized cross-correlation
+
+Received samples
+-> cross-correlation with the analytic preamble by FFT, block by block (overlap-save)
+-> envelope of the correlation, which does not depend on the carrier phase of the received preamble
+-> normalized by the energy of each window and of the preamble: a metric in [0, 1], which does not
+   depend on the gain of the microphone
+-> the largest metric above the threshold, confirmed if no larger one follows within a preamble
+-> sub-sample position of the peak (parabolic interpolation of the envelope)
+*/
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+// the metric of noise is about 1 / sqrt(time-bandwidth product of the preamble), the receive filter
+// chain removes the noise out of the band of the preamble. Measured by `ber::measure`
+// (`test_measure_preamble_threshold`): the chirp preamble gives no false preamble at 0.3, while 0.5
+// misses most of the CSS frames at -10dB. The short Barker 13 preamble needs 0.5.
+pub const DEFAULT_PREAMBLE_THRESHOLD: f32 = 0.3;
+
+pub struct PreambleDetector {
+    preamble_len: usize,
+    preamble_norm: f32,
+    fft_size: usize,
+    // in f64: the rounding error of the FFT follows the loudest window of the block, it would
+    // swamp the correlation of the quiet windows next to it
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+    // conjugate of the spectrum of the zero padded analytic preamble
+    preamble_spectrum: Vec<Complex<f64>>,
+    threshold: f32,
+}
+
+impl PreambleDetector {
+    // @param preamble: the preamble as received, i.e. through the receive filter chain
+    pub fn new(preamble: &[f32], threshold: f32) -> Self {
+        let preamble_len = preamble.len();
+        // each block of the overlap-save gives `fft_size - preamble_len + 1` windows
+        let fft_size = (2 * preamble_len).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let ifft = planner.plan_fft_inverse(fft_size);
+
+        let mut preamble_spectrum: Vec<Complex<f64>> = preamble
+            .iter()
+            .map(|&x| Complex::new(x as f64, 0.0))
+            .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+            .take(fft_size)
+            .collect();
+        fft.process(&mut preamble_spectrum);
+        // analytic signal: the negative frequencies are removed, the positive ones are doubled
+        for (k, x) in preamble_spectrum.iter_mut().enumerate() {
+            if k > fft_size / 2 {
+                *x = Complex::new(0.0, 0.0);
+            } else if k > 0 && k < fft_size / 2 {
+                *x *= 2.0;
+            }
+        }
+        ifft.process(&mut preamble_spectrum);
+        // the Hilbert transform spreads out of the preamble, it is cut to the preamble, so that the
+        // correlation only depends on the samples in the window
+        let preamble: Vec<f64> = preamble.iter().map(|&x| x as f64).collect();
+        let mut quadrature: Vec<f64> = preamble_spectrum[..preamble_len]
+            .iter()
+            .map(|x| x.im / fft_size as f64)
+            .collect();
+        // the cut breaks the orthogonality to the preamble and the norm of the quadrature, which
+        // are restored, so that the metric does not exceed 1
+        let energy: f64 = preamble.iter().map(|x| x * x).sum();
+        let projection: f64 = quadrature
+            .iter()
+            .zip(&preamble)
+            .map(|(q, p)| q * p)
+            .sum::<f64>()
+            / energy;
+        for (q, p) in quadrature.iter_mut().zip(&preamble) {
+            *q -= projection * p;
+        }
+        let scale = (energy / quadrature.iter().map(|q| q * q).sum::<f64>()).sqrt();
+        for (n, x) in preamble_spectrum.iter_mut().enumerate() {
+            *x = if n < preamble_len {
+                Complex::new(preamble[n], quadrature[n] * scale)
+            } else {
+                Complex::new(0.0, 0.0)
+            };
+        }
+        fft.process(&mut preamble_spectrum);
+        let preamble_spectrum = preamble_spectrum.iter().map(|x| x.conj()).collect();
+
+        PreambleDetector {
+            preamble_len,
+            preamble_norm: energy.sqrt() as f32,
+            fft_size,
+            fft,
+            ifft,
+            preamble_spectrum,
+            threshold,
+        }
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    // the false alarm rate against the detection of weak preambles
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    // the envelope of the correlation of the window `samples[i..i + preamble_len]` with the preamble,
+    // for each `i`
+    pub fn correlate(&self, samples: &[f32]) -> Vec<f32> {
+        if samples.len() < self.preamble_len {
+            return vec![];
+        }
+        let window_cnt = samples.len() - self.preamble_len + 1;
+        let step = self.fft_size - self.preamble_len + 1;
+        let mut correlation = Vec::with_capacity(window_cnt);
+
+        let mut block_start = 0;
+        while block_start < window_cnt {
+            let mut buffer: Vec<Complex<f64>> = (0..self.fft_size)
+                .map(|n| Complex::new(*samples.get(block_start + n).unwrap_or(&0.0) as f64, 0.0))
+                .collect();
+            self.fft.process(&mut buffer);
+            for (x, p) in buffer.iter_mut().zip(self.preamble_spectrum.iter()) {
+                *x *= p;
+            }
+            self.ifft.process(&mut buffer);
+
+            // the first `step` outputs of the circular correlation do not wrap around
+            let cnt = step.min(window_cnt - block_start);
+            correlation.extend(
+                buffer[..cnt]
+                    .iter()
+                    .map(|x| (x.norm() / self.fft_size as f64) as f32),
+            );
+            block_start += step;
+        }
+        correlation
+    }
+
+    // the envelope of the correlation normalized by the energy of each window and of the preamble
+    pub fn normalize(&self, samples: &[f32], correlation: &[f32]) -> Vec<f32> {
+        // running energy of the windows, in f64 against the rounding error of the long sums
+        let mut energy: f64 = samples[..self.preamble_len.min(samples.len())]
+            .iter()
+            .map(|&x| (x * x) as f64)
+            .sum();
+        let mut metric = Vec::with_capacity(correlation.len());
+        for (i, &value) in correlation.iter().enumerate() {
+            if i > 0 {
+                let (old, new) = (samples[i - 1], samples[i + self.preamble_len - 1]);
+                energy += (new * new) as f64 - (old * old) as f64;
+            }
+            let norm = energy.max(0.0).sqrt() as f32 * self.preamble_norm;
+            metric.push(if norm > 1e-6 { value / norm } else { 0.0 });
+        }
+        metric
+    }
+}
+
+// the offset (-0.5..0.5) of the peak of `correlation` around `index`, by a parabola through the
+// three samples around it
+pub fn interpolate_peak(correlation: &[f32], index: usize) -> f32 {
+    if index == 0 || index + 1 >= correlation.len() {
+        return 0.0;
+    }
+    let (left, center, right) = (
+        correlation[index - 1],
+        correlation[index],
+        correlation[index + 1],
+    );
+    let denominator = left - 2.0 * center + right;
+    if denominator >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+}
@@ -0,0 +1,76 @@
+/*
+Pulse shaping of the PSK symbols
+
+Symbol (I, Q)
+-> I * sin + Q * cos, multiplied by the envelope of the pulse shape
+-> Output Signal
+
+Received Symbol
+-> correlation with the references multiplied by the same envelope (matched filter)
+-> (I, Q)
+
+A rectangular envelope flips the sinusoid abruptly at the symbol boundaries, whose clicks spread
+over the whole band. The envelopes here fall to 0 at the boundaries, so that the spectrum of each
+symbol is bounded around the carrier.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PulseShape {
+    Rectangular,
+    // raised-cosine edges with the roll-off factor (0..=1): the first and the last `roll-off / 2`
+    // of the symbol rise and fall as a half cosine, 1 is the same as `Hann`
+    RaisedCosine(f32),
+    // Hann window over the whole symbol
+    Hann,
+}
+
+impl Default for PulseShape {
+    fn default() -> Self {
+        PulseShape::Rectangular
+    }
+}
+
+impl PulseShape {
+    // samples of each edge of a symbol of `len` samples
+    pub fn edge_len(&self, len: usize) -> usize {
+        match self {
+            PulseShape::Rectangular => 0,
+            PulseShape::RaisedCosine(roll_off) => {
+                assert!(
+                    (0.0..=1.0).contains(roll_off),
+                    "[PulseShape] the roll-off factor must be in [0, 1]"
+                );
+                (*roll_off * len as f32 / 2.0).round() as usize
+            }
+            PulseShape::Hann => len / 2,
+        }
+    }
+
+    // envelope of a symbol of `len` samples
+    pub fn window(&self, len: usize) -> Vec<f32> {
+        let edge_len = self.edge_len(len);
+        let ramp = rising_edge(edge_len);
+        (0..len)
+            .map(|n| {
+                if n < edge_len {
+                    ramp[n]
+                } else if n >= len - edge_len {
+                    ramp[len - 1 - n]
+                } else {
+                    1.0
+                }
+            })
+            .collect()
+    }
+}
+
+// the rising half of a raised cosine, sampled at the middle of each sample, so that
+// `rising_edge(len)[n] + rising_edge(len)[len - 1 - n] == 1`
+pub fn rising_edge(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|n| {
+            let x = (n as f32 + 0.5) / len as f32;
+            0.5 - 0.5 * (std::f32::consts::PI * x).cos()
+        })
+        .collect()
+}
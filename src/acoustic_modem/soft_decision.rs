@@ -0,0 +1,80 @@
+/*
+Soft decisions of the received bits
+
+Decisions of a frame (`link_metrics::CarrierAccumulator`)
+-> noise of the frame: the power of the error vector of the decisions
+-> LLR of each bit: ln(P(bit = 0) / P(bit = 1)), by the max-log approximation of the distances to
+   the nearest constellation points with the bit at 0 and at 1 (`Constellation::llrs`)
+-> frame decoder (`FrameCodec::decode`): the sign is the hard decision, the magnitude tells which
+   bits to flip first when Reed-Solomon can not decode the hard decision (Chase decoding)
+
+The demodulators which report no decisions (FSK, CSS) give the hard bits with `HARD_LLR`, Chase
+decoding does not flip them. The CRC is checked once on the frame chosen by Chase decoding: trying
+it on every combination of flips would multiply the false frames by 2 ^ `CHASE_BITS`.
+*/
+use crate::utils::Bit;
+use anyhow::Error;
+
+// the LLR of a hard bit, as reliable as a BPSK symbol at about 4dB
+pub const HARD_LLR: f32 = 10.0;
+// the noise is never estimated below it, or a frame without errors would give infinite LLRs
+pub const MIN_NOISE_VAR: f32 = 1e-3;
+// the least reliable bits tried by `chase`, 2 ^ CHASE_BITS - 1 more decodings at most
+pub const CHASE_BITS: usize = 4;
+
+// bit 0 for a positive (or zero) LLR, like the hard slicer
+pub fn hard_bits(llrs: &[f32]) -> Vec<Bit> {
+    llrs.iter()
+        .map(|&llr| if llr >= 0.0 { 0 } else { 1 })
+        .collect()
+}
+
+pub fn hard_llrs(bits: &[Bit]) -> Vec<f32> {
+    bits.iter()
+        .map(|&bit| if bit == 0 { HARD_LLR } else { -HARD_LLR })
+        .collect()
+}
+
+// the indices of the `cnt` bits with the smallest |LLR|
+pub fn least_reliable(llrs: &[f32], cnt: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..llrs.len()).collect();
+    indices.sort_by(|&a, &b| llrs[a].abs().total_cmp(&llrs[b].abs()));
+    indices.truncate(cnt);
+    indices
+}
+
+// decode the hard bits, and if it fails, the hard bits with each combination of the `CHASE_BITS`
+// least reliable bits flipped, then `check` the first success (or return the error of the hard bits)
+// Only the bits less reliable than the others are flipped, none of the hard bits of `hard_llrs`.
+// @param decoder: the decoding which chooses the combination, e.g. Reed-Solomon
+// @param check: the check of the decoded frame which is not used to choose, e.g. the CRC
+pub fn chase<T, U>(
+    llrs: &[f32],
+    decoder: impl Fn(Vec<Bit>) -> Result<T, Error>,
+    check: impl FnOnce(T) -> Result<U, Error>,
+) -> Result<U, Error> {
+    let bits = hard_bits(llrs);
+    let mut result = decoder(bits.clone());
+    if result.is_err() {
+        let max_llr = llrs.iter().fold(0.0f32, |max, llr| max.max(llr.abs()));
+        let mut flips = least_reliable(llrs, CHASE_BITS);
+        flips.retain(|&index| llrs[index].abs() < max_llr);
+        for pattern in 1..1usize << flips.len() {
+            let mut bits = bits.clone();
+            for (k, &index) in flips.iter().enumerate() {
+                if (pattern >> k) & 1 == 1 {
+                    bits[index] ^= 1;
+                }
+            }
+            if let Ok(decoded) = decoder(bits) {
+                println!(
+                    "[chase] decoded with the flips {:b} of {:?}",
+                    pattern, flips
+                );
+                result = Ok(decoded);
+                break;
+            }
+        }
+    }
+    result.and_then(check)
+}
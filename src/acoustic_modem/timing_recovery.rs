@@ -0,0 +1,107 @@
+/*
+Symbol timing recovery of the PSK / QAM symbols (Gardner)
+
+Received Symbol k
+-> correlation of the symbol, and of the window between symbol k - 1 and k (mid window)
+-> timing error: Re{mid * conj(symbol[k - 1] - symbol[k])}, zero if the sampling instant is right
+-> second order loop filter: the start of symbol k + 1, with a fraction of a sample
+-> cubic interpolation of the window at the fractional start (fractional resampler)
+
+The preamble only aligns the sampling instant at the beginning of a frame, the sample clock skew of
+the sound cards moves the symbols away from it by a few samples during a long frame.
+*/
+
+// gains of the loop filter: the sampling instant and the drift per symbol, against the timing error
+const TIMING_KP: f32 = 0.1;
+const TIMING_KI: f32 = 0.003;
+
+pub struct TimingRecovery {
+    symbol_len: usize,
+    // fraction of a sample of the start of the next symbol
+    fraction: f32,
+    // drift of the symbol boundaries in samples per symbol
+    rate: f32,
+}
+
+impl TimingRecovery {
+    pub fn new(symbol_len: usize) -> Self {
+        TimingRecovery {
+            symbol_len,
+            fraction: 0.0,
+            rate: 0.0,
+        }
+    }
+
+    // called when a new frame begins, the preamble aligns the sampling instant again
+    // @param fraction: fraction of a sample of the start of the first symbol, from the preamble
+    pub fn reset(&mut self, fraction: f32) {
+        self.fraction = fraction;
+        self.rate = 0.0;
+    }
+
+    // samples kept before the current symbol for the mid window
+    pub fn margin(&self) -> usize {
+        self.symbol_len / 2 + 2
+    }
+
+    // the symbol starting at `start` + the fraction
+    pub fn window(&self, samples: &[f32], start: usize) -> Vec<f32> {
+        fractional_window(samples, start as isize, self.fraction, self.symbol_len)
+    }
+
+    // the window between the previous symbol and the symbol starting at `start` + the fraction
+    pub fn mid_window(&self, samples: &[f32], start: usize) -> Vec<f32> {
+        let start = start as isize - (self.symbol_len / 2) as isize;
+        fractional_window(samples, start, self.fraction, self.symbol_len)
+    }
+
+    // the whole samples from the current symbol to the next one
+    // @param timing_error: the sampling instant of the current symbol is late by these samples,
+    // None if the symbol tells nothing about the timing
+    pub fn advance(&mut self, timing_error: Option<f32>) -> usize {
+        let mut step = self.symbol_len as f32 + self.rate;
+        if let Some(timing_error) = timing_error {
+            // one bad symbol can not move the sampling instant too far
+            let limit = self.symbol_len as f32 / 4.0;
+            let timing_error = timing_error.clamp(-limit, limit);
+            self.rate -= TIMING_KI * timing_error;
+            step -= TIMING_KP * timing_error;
+        }
+        let position = self.fraction + step.max(1.0);
+        self.fraction = position.fract();
+        position.floor() as usize
+    }
+}
+
+// `len` samples starting at `start + fraction` (0 <= fraction < 1), by cubic Lagrange interpolation
+// The samples out of `samples` are taken as 0.
+pub fn fractional_window(samples: &[f32], start: isize, fraction: f32, len: usize) -> Vec<f32> {
+    let sample = |index: isize| -> f32 {
+        if index < 0 || index as usize >= samples.len() {
+            0.0
+        } else {
+            samples[index as usize]
+        }
+    };
+    if fraction == 0.0 {
+        return (0..len as isize).map(|n| sample(start + n)).collect();
+    }
+
+    // coefficients of x[n - 1], x[n], x[n + 1], x[n + 2]
+    let d = fraction;
+    let coefficients = [
+        -d * (d - 1.0) * (d - 2.0) / 6.0,
+        (d + 1.0) * (d - 1.0) * (d - 2.0) / 2.0,
+        -(d + 1.0) * d * (d - 2.0) / 2.0,
+        (d + 1.0) * d * (d - 1.0) / 6.0,
+    ];
+    (0..len as isize)
+        .map(|n| {
+            coefficients
+                .iter()
+                .enumerate()
+                .map(|(k, c)| c * sample(start + n + k as isize - 1))
+                .sum()
+        })
+        .collect()
+}
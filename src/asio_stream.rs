@@ -5,6 +5,7 @@ use cpal::{
 use futures::{FutureExt, Sink, SinkExt, Stream};
 use rodio::{OutputStream, Source, SupportedStreamConfig};
 use std::{
+    collections::VecDeque,
     iter::ExactSizeIterator,
     time::Duration,
 };
@@ -194,6 +195,55 @@ impl Stream for InputAudioStream {
     }
 }
 
+/* struct: LoopbackAudioStream
+description: This struct is used to feed samples produced in software (e.g. by `Modulator::bits_2_wave`)
+into a receiver, as if they were recorded by an input device. It is a mono stream that ends when all the
+chunks are consumed.
+fields:
+- chunks: VecDeque<Vec<f32>>
+impl:
+- new(
+    chunks: Vec<Vec<f32>>
+): This function creates a new LoopbackAudioStream yielding the given chunks in order.
+- split(
+    samples: &[f32],
+    chunk_size: usize
+): This function splits the samples into chunks of `chunk_size`, like the callbacks of a sound card,
+  i.e. the chunks of `new`.
+
+- `Stream` trait: for field `chunks`.
+*/
+pub struct LoopbackAudioStream {
+    chunks: VecDeque<Vec<f32>>,
+}
+
+impl LoopbackAudioStream {
+    pub fn new(chunks: Vec<Vec<f32>>) -> Self {
+        return Self {
+            chunks: chunks.into(),
+        };
+    }
+
+    pub fn split(samples: &[f32], chunk_size: usize) -> Vec<Vec<f32>> {
+        assert!(chunk_size > 0);
+        return samples
+            .chunks(chunk_size)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+    }
+}
+
+impl Stream for LoopbackAudioStream {
+    type Item = Vec<f32>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> futures::task::Poll<Option<Self::Item>> {
+        futures::task::Poll::Ready(self.chunks.pop_front())
+    }
+}
+
 /* struct: OutputAudioStream
 description: This struct is used to create an output audio stream.
 fields:
@@ -318,41 +368,34 @@ pub fn show_devices() {
     }
 }
 
-pub async fn read_wav_into_vec(filename: &str) -> (Vec<f32>, u32) {
-    let mut reader = hound::WavReader::open(filename).unwrap();
+// the samples of a WAV file in [-1, 1], the channels are mixed down to mono
+pub async fn read_wav_into_vec(filename: &str) -> Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(filename)?;
     let spec = reader.spec();
 
     println!(
-        "Read {filename} with sample format: {} and sample rate: {}",
-        match spec.sample_format {
-            hound::SampleFormat::Int => match spec.bits_per_sample {
-                8 => "i8",
-                16 => "i16",
-                _ => panic!("unsupported bits per sample"),
-            },
-            hound::SampleFormat::Float => "f32",
-        },
-        spec.sample_rate
+        "Read {filename} with sample format: {:?} {} bits, {} channels and sample rate: {}",
+        spec.sample_format, spec.bits_per_sample, spec.channels, spec.sample_rate
     );
     let samples: Vec<f32> = match spec.sample_format {
-        hound::SampleFormat::Int => match spec.bits_per_sample {
-            8 => {
-                let samples: Vec<i8> = reader.samples::<i8>().map(|s| s.unwrap()).collect();
-                samples.iter().map(|&s| s as f32 / i8::MAX as f32).collect()
-            }
-            16 => {
-                let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
-                samples
-                    .iter()
-                    .map(|&s| s as f32 / i16::MAX as f32)
-                    .collect()
-            }
-            _ => panic!("unsupported bits per sample"),
-        },
-        hound::SampleFormat::Float => reader.samples::<f32>().map(|s| s.unwrap()).collect(),
+        hound::SampleFormat::Int => {
+            let max = ((1_i64 << (spec.bits_per_sample - 1)) - 1) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / max))
+                .collect::<Result<_, _>>()?
+        }
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
     };
 
-    return (samples, spec.sample_rate);
+    // the samples of the channels are interleaved
+    let channels = spec.channels as usize;
+    let samples = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    return Ok((samples, spec.sample_rate));
 }
 
 pub async fn read_wav(filename: &str) -> (AudioTrack<std::vec::IntoIter<f32>>, u32) {
@@ -362,9 +405,10 @@ pub async fn read_wav(filename: &str) -> (AudioTrack<std::vec::IntoIter<f32>>, u
     };
     use cpal::{SampleRate, SupportedStreamConfig};
 
-    let (samples, sample_rate) = read_wav_into_vec(filename).await;
+    let (samples, sample_rate) = read_wav_into_vec(filename).await.unwrap();
 
-    let host = cpal::host_from_id(HostId::Asio).expect("failed to initialise ASIO host");
+    // let host = cpal::host_from_id(HostId::Asio).expect("failed to initialise ASIO host");
+    let host = cpal::default_host();
     let device = host
         .default_input_device()
         .expect("failed to find input device");
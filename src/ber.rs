@@ -0,0 +1,548 @@
+/*
+Random payloads
+-> Modulator
+-> Channel Model (-> WAV file)
+-> Demodulation2
+-> BER / FER / preamble statistics
+*/
+use crate::acoustic_modem::channel::{ChannelConfig, ChannelModel};
+use crate::acoustic_modem::constellation::Constellation;
+use crate::acoustic_modem::css;
+use crate::acoustic_modem::demodulation::Demodulation2;
+use crate::acoustic_modem::modulation::{self, Modulation, Modulator};
+use crate::acoustic_modem::phy_frame::{self, FrameStatus};
+use crate::acoustic_modem::preamble::{
+    BarkerPreamble, MSequencePreamble, Preamble, ZadoffChuPreamble,
+};
+use crate::asio_stream;
+use crate::pa1;
+use crate::utils::{self, Bit};
+use anyhow::{Error, Result};
+use hound::{WavSpec, WavWriter};
+use plotters::prelude::*;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+// the lower bound of the BER axis, a BER of 0 is drawn here
+const BER_FLOOR: f64 = 1e-5;
+// the size of the chunks fed into the demodulator, like the callbacks of a sound card
+const CHUNK_SIZE: usize = 512;
+
+// carrier settings of a link, see `pa1::CARRIER_LOW`, `pa1::CARRIER_INTERVAL`, `pa1::CARRIER_CNT`
+// and `modulation::REDUNDANT_PERIODS`
+#[derive(Clone, Debug)]
+pub struct LinkConfig {
+    pub carrier_low: u32,
+    pub carrier_interval: u32,
+    pub carrier_cnt: u32,
+    pub redundant_periods: usize,
+    // of PSK / QAM and OFDM, ignored by the other modulations
+    pub constellation: Constellation,
+    // OFDM on `carrier_cnt` subcarriers, FSK on `carrier_cnt` tones, the others on the lowest carrier
+    pub modulation: Modulation,
+    // the preamble of the frames, None for the chirp of `phy_frame::gen_preamble`
+    pub preamble: Option<Arc<dyn Preamble>>,
+    // threshold of the preamble detection, None for `DEFAULT_PREAMBLE_THRESHOLD`
+    pub preamble_threshold: Option<f32>,
+}
+
+impl LinkConfig {
+    pub fn label(&self) -> String {
+        format!(
+            "{}Hz+{}Hz x{}, {} periods, {}",
+            self.carrier_low,
+            self.carrier_interval,
+            self.carrier_cnt,
+            self.redundant_periods,
+            match &self.modulation {
+                Modulation::Psk => format!("{:?}", self.constellation),
+                Modulation::Ofdm => format!("OFDM {:?}", self.constellation),
+                Modulation::Fsk => "FSK".to_string(),
+                Modulation::Css(spreading_factor) => format!("CSS SF{}", spreading_factor),
+                Modulation::Dsss(pn_code) => format!("DSSS {:?}", pn_code),
+            }
+        ) + &self
+            .preamble
+            .as_ref()
+            .map_or(String::new(), |preamble| format!(", {:?}", preamble))
+            + &self.preamble_threshold.map_or(String::new(), |threshold| {
+                format!(", threshold {}", threshold)
+            })
+    }
+}
+
+// how the modulated signal reaches the demodulator
+#[derive(Clone, Debug)]
+pub enum Medium {
+    // through the channel model in memory
+    Channel,
+    // through the channel model, then written into the WAV file and read back
+    WavRoundTrip(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BerResult {
+    pub trials: usize,
+    pub bits: usize,
+    pub bit_errors: usize,
+    // frames sent, one on each carrier per trial with OFDM
+    pub frames: usize,
+    // frames sent but not received with a matching CRC
+    pub frame_errors: usize,
+    pub missed_preambles: usize,
+    pub false_preambles: usize,
+    // seconds of signal on air
+    pub air_time: f64,
+}
+
+impl BerResult {
+    pub fn ber(&self) -> f64 {
+        self.bit_errors as f64 / self.bits.max(1) as f64
+    }
+
+    pub fn fer(&self) -> f64 {
+        self.frame_errors as f64 / self.frames.max(1) as f64
+    }
+
+    // correctly received bits per second of signal
+    pub fn throughput(&self) -> f64 {
+        if self.air_time == 0.0 {
+            return 0.0;
+        }
+        (self.bits - self.bit_errors) as f64 / self.air_time
+    }
+}
+
+// count the different bits; the missing or redundant bits are counted as errors
+pub fn count_bit_errors(sent: &Vec<Bit>, received: &Vec<Bit>) -> usize {
+    let diff = sent
+        .iter()
+        .zip(received.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    diff + (sent.len() as isize - received.len() as isize).unsigned_abs()
+}
+
+// compare two files of '0' / '1' characters, e.g. `output.txt` and `testset/data.txt`
+pub fn compare_bit_files(file_1: &str, file_2: &str) -> Result<usize> {
+    let mut bits = vec![];
+    for filename in [file_1, file_2] {
+        let mut file = File::open(filename)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        // the line breaks (and the carriage returns of CRLF) are not bits
+        let mut file_bits = vec![];
+        for c in content.chars().filter(|c| !c.is_whitespace()) {
+            match c {
+                '0' | '1' => file_bits.push(c as Bit - b'0'),
+                _ => {
+                    let err_msg = format!("{}: not a bit: {:?}", filename, c);
+                    return Err(Error::msg(err_msg));
+                }
+            }
+        }
+        bits.push(file_bits);
+    }
+
+    if bits[0].len() != bits[1].len() {
+        println!(
+            "Files have different lengths {}",
+            (bits[0].len() as isize - bits[1].len() as isize).abs()
+        );
+    }
+    let diff_count = count_bit_errors(&bits[0], &bits[1]);
+    println!("Number of different bits: {}", diff_count);
+
+    return Ok(diff_count);
+}
+
+fn write_wav(filename: &str, samples: &Vec<f32>, sample_rate: u32) {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(filename, spec).unwrap();
+    for &sample in samples {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+// transmit `trials` random payloads, one frame on each carrier per payload
+pub async fn measure(
+    link_config: &LinkConfig,
+    channel_config: ChannelConfig,
+    medium: &Medium,
+    trials: usize,
+    seed: u64,
+) -> BerResult {
+    let sample_rate = pa1::SAMPLE_RATE;
+    let enable_ofdm = link_config.modulation == Modulation::Ofdm;
+    let carrier_config = vec![
+        link_config.carrier_low,
+        link_config.carrier_interval,
+        link_config.carrier_cnt,
+    ];
+    let frame_len = if enable_ofdm {
+        phy_frame::FRAME_PAYLOAD_LENGTH
+    } else {
+        phy_frame::FRAME_LENGTH_NO_ENCODING
+    };
+    // one frame on each carrier with OFDM, one frame on all the tones with FSK
+    let frame_cnt = if enable_ofdm {
+        link_config.carrier_cnt as usize
+    } else {
+        1
+    };
+    let payload_len = phy_frame::MAX_FRAME_DATA_LENGTH * frame_cnt;
+
+    let mut modulator = Modulator::new_loopback(carrier_config.clone(), sample_rate, enable_ofdm);
+    modulator.set_redundant_periods(link_config.redundant_periods);
+    modulator.set_constellation(link_config.constellation);
+    modulator.set_modulation(link_config.modulation.clone());
+    if let Some(preamble) = &link_config.preamble {
+        modulator.set_preamble(preamble.as_ref());
+    }
+    let mut channel = ChannelModel::new(channel_config, sample_rate, seed);
+    let output_file = std::env::temp_dir().join("ber_output.txt");
+
+    let mut result = BerResult::default();
+    for _ in 0..trials {
+        let data = utils::gen_random_data(payload_len);
+        let wave = modulator
+            .bits_2_wave(
+                utils::read_data_2_compressed_u8(data.clone()),
+                data.len() as isize,
+            )
+            .await;
+        result.air_time += wave.len() as f64 / sample_rate as f64;
+
+        let mut received = channel.transmit(&wave);
+        if let Medium::WavRoundTrip(filename) = medium {
+            write_wav(filename, &received, sample_rate);
+            received = asio_stream::read_wav_into_vec(filename).await.unwrap().0;
+        }
+
+        let mut demodulator = Demodulation2::new_loopback(
+            carrier_config.clone(),
+            sample_rate,
+            output_file.to_str().unwrap(),
+            link_config.redundant_periods,
+            enable_ofdm,
+        );
+        demodulator.set_constellation(link_config.constellation);
+        demodulator.set_modulation(link_config.modulation.clone());
+        if let Some(preamble) = &link_config.preamble {
+            demodulator.set_preamble(preamble.as_ref());
+        }
+        if let Some(threshold) = link_config.preamble_threshold {
+            demodulator.set_preamble_threshold(threshold);
+        }
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = asio_stream::LoopbackAudioStream::split(&received, CHUNK_SIZE);
+        let frame_metrics = demodulator
+            .listening(
+                false,
+                frame_len,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+        let detected_frames = frame_metrics.len();
+        let received_frames = frame_metrics
+            .iter()
+            .flat_map(|metrics| metrics.carriers.iter())
+            .filter(|carrier| carrier.status == FrameStatus::Ok)
+            .count();
+
+        // all the carriers share one preamble
+        let bit_errors = count_bit_errors(&data, &decoded_data);
+        result.trials += 1;
+        result.bits += data.len();
+        result.bit_errors += bit_errors.min(data.len());
+        result.frames += frame_cnt;
+        result.frame_errors += frame_cnt.saturating_sub(received_frames);
+        result.missed_preambles += if detected_frames == 0 { 1 } else { 0 };
+        result.false_preambles += detected_frames.saturating_sub(1);
+    }
+
+    return result;
+}
+
+// measure every link config at every SNR, write the results into `csv_file` and the BER-vs-SNR chart into `svg_file`
+pub async fn sweep(
+    link_configs: &Vec<LinkConfig>,
+    snr_list: &Vec<f32>,
+    channel_config: ChannelConfig,
+    medium: &Medium,
+    trials: usize,
+    csv_file: &str,
+    svg_file: &str,
+) -> Result<Vec<(LinkConfig, f32, BerResult)>> {
+    let mut results = vec![];
+    for link_config in link_configs {
+        for (i, &snr_db) in snr_list.iter().enumerate() {
+            let channel_config = ChannelConfig {
+                snr_db: Some(snr_db),
+                ..channel_config.clone()
+            };
+            let result = measure(link_config, channel_config, medium, trials, i as u64).await;
+            println!(
+                "[ber sweep] {}, snr: {} dB, ber: {}, fer: {}, missed preambles: {}, false preambles: {}, throughput: {} bps",
+                link_config.label(),
+                snr_db,
+                result.ber(),
+                result.fer(),
+                result.missed_preambles,
+                result.false_preambles,
+                result.throughput()
+            );
+            results.push((link_config.clone(), snr_db, result));
+        }
+    }
+
+    write_csv(&results, csv_file)?;
+    plot_ber(&results, snr_list, svg_file)?;
+
+    return Ok(results);
+}
+
+fn write_csv(results: &Vec<(LinkConfig, f32, BerResult)>, csv_file: &str) -> Result<()> {
+    let mut writer = File::create(csv_file)?;
+    writeln!(
+        writer,
+        "carrier_low,carrier_interval,carrier_cnt,redundant_periods,constellation,modulation,preamble,preamble_threshold,snr_db,trials,bits,bit_errors,frames,frame_errors,ber,fer,missed_preambles,false_preambles,throughput_bps"
+    )?;
+    for (link_config, snr_db, result) in results {
+        writeln!(
+            writer,
+            "{},{},{},{},{:?},\"{:?}\",\"{}\",{},{},{},{},{},{},{},{},{},{},{},{}",
+            link_config.carrier_low,
+            link_config.carrier_interval,
+            link_config.carrier_cnt,
+            link_config.redundant_periods,
+            link_config.constellation,
+            link_config.modulation,
+            link_config
+                .preamble
+                .as_ref()
+                .map_or(String::new(), |preamble| format!("{:?}", preamble)),
+            link_config
+                .preamble_threshold
+                .map_or(String::new(), |threshold| threshold.to_string()),
+            snr_db,
+            result.trials,
+            result.bits,
+            result.bit_errors,
+            result.frames,
+            result.frame_errors,
+            result.ber(),
+            result.fer(),
+            result.missed_preambles,
+            result.false_preambles,
+            result.throughput()
+        )?;
+    }
+    Ok(())
+}
+
+fn plot_ber(
+    results: &Vec<(LinkConfig, f32, BerResult)>,
+    snr_list: &Vec<f32>,
+    svg_file: &str,
+) -> Result<()> {
+    let snr_min = snr_list.iter().cloned().fold(f32::MAX, f32::min) as f64;
+    let snr_max = snr_list.iter().cloned().fold(f32::MIN, f32::max) as f64;
+
+    let drawing_area = SVGBackend::new(svg_file, (1000, 600)).into_drawing_area();
+    drawing_area
+        .fill(&WHITE)
+        .map_err(|e| Error::msg(e.to_string()))?;
+    let mut chart = ChartBuilder::on(&drawing_area)
+        .caption("BER vs SNR", ("sans-serif", 30).into_font())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(snr_min..snr_max, (BER_FLOOR..1.0).log_scale())
+        .map_err(|e| Error::msg(e.to_string()))?;
+    chart
+        .configure_mesh()
+        .x_desc("SNR (dB)")
+        .y_desc("BER")
+        .draw()
+        .map_err(|e| Error::msg(e.to_string()))?;
+
+    let labels: Vec<String> = results.iter().map(|(l, _, _)| l.label()).collect();
+    let mut drawn = vec![];
+    for label in labels {
+        if drawn.contains(&label) {
+            continue;
+        }
+        let color = Palette99::pick(drawn.len()).to_rgba();
+        let points: Vec<(f64, f64)> = results
+            .iter()
+            .filter(|(l, _, _)| l.label() == label)
+            .map(|(_, snr_db, result)| (*snr_db as f64, result.ber().max(BER_FLOOR)))
+            .collect();
+        chart
+            .draw_series(LineSeries::new(points, color.stroke_width(2)))
+            .map_err(|e| Error::msg(e.to_string()))?
+            .label(label.clone())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        drawn.push(label);
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .border_style(BLACK)
+        .background_style(WHITE.mix(0.8))
+        .draw()
+        .map_err(|e| Error::msg(e.to_string()))?;
+    drawing_area
+        .present()
+        .map_err(|e| Error::msg(e.to_string()))?;
+
+    Ok(())
+}
+
+// the default sweep around the carrier settings of PA1
+pub async fn default_sweep(trials: usize) -> Result<()> {
+    let mut link_configs = vec![];
+    for carrier_low in [1200, pa1::CARRIER_LOW, 4800] {
+        for redundant_periods in [1, modulation::REDUNDANT_PERIODS, 4] {
+            link_configs.push(LinkConfig {
+                carrier_low,
+                carrier_interval: pa1::CARRIER_INTERVAL,
+                carrier_cnt: 1,
+                redundant_periods,
+                constellation: Constellation::Bpsk,
+                modulation: Modulation::Psk,
+                preamble: None,
+                preamble_threshold: None,
+            });
+        }
+    }
+    for constellation in [
+        Constellation::Qpsk,
+        Constellation::Psk8,
+        Constellation::Qam16,
+        Constellation::Dbpsk,
+        Constellation::Dqpsk,
+    ] {
+        link_configs.push(LinkConfig {
+            carrier_low: pa1::CARRIER_LOW,
+            carrier_interval: pa1::CARRIER_INTERVAL,
+            carrier_cnt: 1,
+            redundant_periods: modulation::REDUNDANT_PERIODS,
+            constellation,
+            modulation: Modulation::Psk,
+            preamble: None,
+            preamble_threshold: None,
+        });
+    }
+    // the subcarrier spacing sets the FFT size, see `OfdmConfig::new`
+    for carrier_low in [1200, pa1::CARRIER_LOW] {
+        for carrier_interval in [600, pa1::CARRIER_INTERVAL, 1200] {
+            link_configs.push(LinkConfig {
+                carrier_low,
+                carrier_interval,
+                carrier_cnt: pa1::CARRIER_CNT,
+                redundant_periods: modulation::REDUNDANT_PERIODS,
+                constellation: Constellation::Bpsk,
+                modulation: Modulation::Ofdm,
+                preamble: None,
+                preamble_threshold: None,
+            });
+        }
+    }
+    // 4-FSK on the same carriers
+    link_configs.push(LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: pa1::CARRIER_CNT,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Fsk,
+        preamble: None,
+        preamble_threshold: None,
+    });
+    // chirp spread spectrum, for the SNR where PSK is unusable
+    for spreading_factor in [css::CSS_DEFAULT_SPREADING_FACTOR, 9] {
+        link_configs.push(LinkConfig {
+            carrier_low: pa1::CARRIER_LOW,
+            carrier_interval: pa1::CARRIER_INTERVAL,
+            carrier_cnt: 1,
+            redundant_periods: modulation::REDUNDANT_PERIODS,
+            constellation: Constellation::Bpsk,
+            modulation: Modulation::Css(spreading_factor),
+            preamble: None,
+            preamble_threshold: None,
+        });
+    }
+    // the preamble families against the default chirp, for the missed / false preambles
+    // the noise and the data reach 0.45 through the matched filter of the short Barker 13
+    let preambles: Vec<(Arc<dyn Preamble>, Option<f32>)> = vec![
+        (
+            Arc::new(BarkerPreamble {
+                len: 13,
+                carrier: 3000.0,
+                chip_len: 24,
+            }),
+            Some(0.5),
+        ),
+        (
+            Arc::new(MSequencePreamble {
+                degree: 6,
+                carrier: 4000.0,
+                chip_len: 12,
+            }),
+            None,
+        ),
+        (
+            Arc::new(ZadoffChuPreamble {
+                root: 25,
+                len: 63,
+                carrier: 4000.0,
+                chip_len: 12,
+            }),
+            None,
+        ),
+    ];
+    for (preamble, preamble_threshold) in preambles {
+        link_configs.push(LinkConfig {
+            carrier_low: pa1::CARRIER_LOW,
+            carrier_interval: pa1::CARRIER_INTERVAL,
+            carrier_cnt: 1,
+            redundant_periods: modulation::REDUNDANT_PERIODS,
+            constellation: Constellation::Bpsk,
+            modulation: Modulation::Psk,
+            preamble: Some(preamble),
+            preamble_threshold,
+        });
+    }
+    let snr_list = vec![-10.0, -5.0, 0.0, 5.0, 10.0, 20.0];
+    let channel_config = ChannelConfig {
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        ..Default::default()
+    };
+
+    sweep(
+        &link_configs,
+        &snr_list,
+        channel_config,
+        &Medium::Channel,
+        trials,
+        "testset/ber.csv",
+        "testset/ber.svg",
+    )
+    .await?;
+
+    return Ok(());
+}
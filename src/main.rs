@@ -1,5 +1,6 @@
 mod acoustic_modem;
 mod asio_stream;
+mod ber;
 mod pa0;
 mod pa1;
 mod tests;
@@ -11,7 +12,7 @@ fn help() {
     println!("  -h, --help: Show this help message");
     println!("  -p=N, --pa=N: Select PA N to demonstrate");
     println!("  -o, --objective=N: Select an objective N in a specified PA to demonstrate. If no PA specified, this will be ignored.");
-    println!("  -t=<str>, --type=<str>: Additional type for the selected PA: send, send_file");
+    println!("  -t=<str>, --type=<str>: Additional type for the selected PA: send, send_file, receive_file, decode_wav[:<file>], ber, diff");
     println!("  -d, -device: Show available ASIO devices");
     println!("  -g[=N], --generate[=N]: Generate a random data file with N (default 10000) bits");
 }
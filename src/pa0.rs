@@ -120,7 +120,7 @@ async fn obj_2(host: &Host) {
 
 pub async fn pa0(sel: i32) -> Result<u32> {
     let host = cpal::default_host();
-    let host = cpal::host_from_id(cpal::HostId::Asio).unwrap();
+    // let host = cpal::host_from_id(cpal::HostId::Asio).unwrap();
     let available_sel = vec![0, 1, 2];
     if !available_sel.contains(&sel) {
         return Err(Error::msg("Invalid selection"));
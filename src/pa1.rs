@@ -1,4 +1,5 @@
 use crate::acoustic_modem::demodulation::Demodulation2;
+use crate::ber;
 use crate::acoustic_modem::modulation;
 use crate::acoustic_modem::modulation::Modulator;
 use crate::acoustic_modem::phy_frame;
@@ -11,13 +12,13 @@ use tokio::time::{self, Duration};
 //     Device, Host, HostId, SampleRate, SupportedStreamConfig,
 // };
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::vec;
 
-const CARRIER_LOW: u32 = 2400;
-const CARRIER_INTERVAL: u32 = 1000;
-const CARRIER_CNT: u32 = 4;
-const SAMPLE_RATE: u32 = 48000;
+pub const CARRIER_LOW: u32 = 2400;
+pub const CARRIER_INTERVAL: u32 = 1000;
+pub const CARRIER_CNT: u32 = 4;
+pub const SAMPLE_RATE: u32 = 48000;
 
 pub async fn obj_2() -> Result<u32> {
     let mut modulator_1 = Modulator::new(vec![1000, 10000], 48000, false);
@@ -104,13 +105,14 @@ pub async fn obj_3_recv_file() -> Result<u32> {
         SAMPLE_RATE,
         "output.txt",
         modulation::REDUNDANT_PERIODS,
+        false,
     );
 
     let mut decoded_data = vec![];
     let mut debug_vec = vec![];
     let handle = demodulator.listening(
         true,
-        phy_frame::MAX_FRAME_DATA_LENGTH + phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING,
+        phy_frame::FRAME_LENGTH_NO_ENCODING,
         &mut decoded_data,
         &mut debug_vec,
         vec![],
@@ -123,6 +125,27 @@ pub async fn obj_3_recv_file() -> Result<u32> {
     return Ok(0);
 }
 
+// decode a recording of `obj_3_send_file` offline
+// output.txt: the decoded bits, output.bin: the decoded bytes
+pub async fn obj_3_decode_wav(filename: &str) -> Result<u32> {
+    let mut demodulator = Demodulation2::new_loopback(
+        vec![CARRIER_LOW, CARRIER_INTERVAL, CARRIER_CNT],
+        SAMPLE_RATE,
+        "output.txt",
+        modulation::REDUNDANT_PERIODS,
+        true,
+    );
+
+    let decoded_data = demodulator
+        .decode_wav(filename, phy_frame::FRAME_PAYLOAD_LENGTH, true)
+        .await?;
+
+    let mut file = File::create("output.bin")?;
+    file.write_all(&utils::read_data_2_compressed_u8(decoded_data))?;
+
+    return Ok(0);
+}
+
 pub async fn pa1(sel: i32, additional_type: &str) -> Result<u32> {
     let available_sel = vec![0, 1, 2, 3];
     if !available_sel.contains(&sel) {
@@ -184,6 +207,37 @@ pub async fn pa1(sel: i32, additional_type: &str) -> Result<u32> {
                     }
                 }
             }
+            t if t.starts_with("decode_wav") => {
+                // -t=decode_wav[:<file>]
+                let filename = match t.split_once(':') {
+                    Some((_, filename)) => filename,
+                    None => "testset/send.wav",
+                };
+                println!("Objective 3 start");
+                match obj_3_decode_wav(filename).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error: {}", e);
+                    }
+                }
+                println!("Objective 3 end");
+            }
+            "ber" => {
+                println!("Objective 3 start");
+                match ber::default_sweep(20).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error: {}", e);
+                    }
+                }
+                println!("Objective 3 end");
+            }
+            "diff" => match ber::compare_bit_files("output.txt", "testset/data.txt") {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error: {}", e);
+                }
+            },
             _ => {
                 println!("Unsupported function.");
             }
@@ -5,3 +5,7 @@
 
 #[cfg(test)]
 pub mod test_acoustic_modem;
+
+
+#[cfg(test)]
+pub mod test_ber;
@@ -4,10 +4,24 @@ use std::time::Duration;
 use std::vec;
 
 use crate::acoustic_modem::demodulation::{self, Demodulation2};
-use crate::acoustic_modem::modulation::Modulator;
-use crate::acoustic_modem::{modulation, phy_frame};
+use crate::acoustic_modem::modulation::{Modulation, Modulator};
+use crate::acoustic_modem::channel::{self, ChannelConfig, ChannelModel};
+use crate::acoustic_modem::constellation::{Constellation, DifferentialDetector};
+use crate::acoustic_modem::convolutional::ConvCode;
+use crate::acoustic_modem::dsss::PnCode;
+use crate::acoustic_modem::equalizer::EqualizerConfig;
+use crate::acoustic_modem::frame_codec::{FrameCodec, RsCode};
+use crate::acoustic_modem::interleaver::Interleaver;
+use crate::acoustic_modem::link_metrics::{self, CarrierAccumulator, SymbolDecision};
+use crate::acoustic_modem::pulse_shaping::PulseShape;
+use crate::acoustic_modem::phy_frame::{FrameHeader, FrameStatus, FrameType, PHYFrame};
+use crate::acoustic_modem::{crc, css, modulation, phy_frame, soft_decision};
+use crate::ber::count_bit_errors;
+use crate::asio_stream::LoopbackAudioStream;
 use crate::utils::{self, read_data_2_compressed_u8};
 use plotters::prelude::*;
+use rand::SeedableRng;
+use rand_distr::Distribution;
 use tokio::time;
 
 use hound::{WavSpec, WavWriter};
@@ -152,7 +166,7 @@ fn test_simple_gen() {
 
 #[tokio::test]
 async fn test_simple_listen() {
-    let mut demodulator = Demodulation2::new(CONFIG.into(), 48000, "output.txt", REDUNDENT);
+    let mut demodulator = Demodulation2::new(CONFIG.into(), 48000, "output.txt", REDUNDENT, false);
 
     let mut debug_vec = vec![];
 
@@ -168,7 +182,7 @@ async fn test_simple_listen() {
 
     // println!("ref: {:?}", ref_data);
     // loop {
-        let res = demodulator.simple_listen(true, &mut debug_vec, LEN, PADDING).await;
+        let res = demodulator.simple_listen(true, &mut debug_vec, LEN, PADDING, vec![]).await;
         // let mut diff_num = 0;
         // for i in 0..ref_data.len() {
         //     if ref_data[i] != res[i] {
@@ -216,6 +230,7 @@ async fn test_seconds_listening() {
         48000,
         "output.txt",
         modulation::REDUNDANT_PERIODS,
+        false,
     );
 
     let mut decoded_data = vec![];
@@ -261,19 +276,2037 @@ fn read_wav_to_array(file_path: &str) -> Vec<f32> {
 #[tokio::test]
 async fn test_ofdm_listen() {
     let mut demodulator = Demodulation2::new(
-        CONFIG.into(), 48000, "output.txt", modulation::REDUNDANT_PERIODS);
+        CONFIG.into(), 48000, "output.txt", modulation::REDUNDANT_PERIODS, true);
     
     let mut decoded_data = vec![];
     let mut debug_vec = vec![];
     let wav_data = vec![read_wav_to_array("test.wav"), vec![0.0, 0.0], vec![]];
     println!("wav_data len: {}", wav_data[0].len());
-    let handle = demodulator.listening(true, phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING + phy_frame::MAX_FRAME_DATA_LENGTH, &mut decoded_data, &mut debug_vec, wav_data);
+    let handle = demodulator.listening(true, FrameCodec::default().payload_len(), &mut decoded_data, &mut debug_vec, wav_data);
     let handle = time::timeout(Duration::from_secs(5), handle);
-    handle.await.unwrap_err();
+    handle.await.unwrap();
     let mut writer = File::create("wav_data.txt").unwrap();
     println!("debug vec len: {}", debug_vec.len());
     for sample in &debug_vec{
         writeln!(writer, "{}", sample).unwrap();
     }
     // plot(debug_vec, "recv_wav.svg").unwrap();   
-}
\ No newline at end of file
+}
+
+fn loopback_output_file(name: &str) -> String {
+    std::env::temp_dir().join(name).to_str().unwrap().to_string()
+}
+
+// the input of `listening`, in chunks like the callbacks of a sound card
+fn loopback_chunks(wave: &[f32]) -> Vec<Vec<f32>> {
+    LoopbackAudioStream::split(wave, 512)
+}
+
+#[tokio::test]
+async fn test_loopback_simple_listen() {
+    let simple_frame = phy_frame::SimpleFrame::new(CARRIER, LEN);
+    let mut wave = simple_frame.into_audio(REDUNDENT, PADDING);
+    wave.extend(vec![0.0; 1000]);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        CONFIG.into(),
+        48000,
+        &loopback_output_file("loopback_simple_output.txt"),
+        REDUNDENT,
+        false,
+    );
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    let res = demodulator
+        .simple_listen(false, &mut debug_vec, LEN, PADDING, test_data)
+        .await;
+
+    assert_eq!(&res, simple_frame.data());
+}
+
+#[tokio::test]
+async fn test_loopback_listening() {
+    let config = vec![CARRIER, 6000, 1];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3 + 20);
+
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    let mut wave = modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await;
+    wave.extend(vec![0.0; 1000]);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config,
+        48000,
+        &loopback_output_file("loopback_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        false,
+    );
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+
+    assert_eq!(decoded_data, data);
+}
+
+// transmit `data` through the loopback with the channel model, return the decoded data
+async fn loopback_through_channel(data: &Vec<u8>, channel_config: ChannelConfig, seed: u64) -> Vec<u8> {
+    let config = vec![CARRIER, 6000, 1];
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    let wave = modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await;
+
+    let mut channel = ChannelModel::new(channel_config, 48000, seed);
+    let wave = channel.transmit(&wave);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config,
+        48000,
+        &loopback_output_file("channel_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        false,
+    );
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+
+    decoded_data
+}
+
+#[test]
+fn test_channel_deterministic() {
+    let signal: Vec<f32> = (0..4800)
+        .map(|x| (2.0 * std::f32::consts::PI * x as f32 / 48000.0 * CARRIER as f32).sin())
+        .collect();
+    let channel_config = ChannelConfig {
+        attenuation: 0.5,
+        echo_taps: vec![(30, 0.3), (100, 0.1)],
+        freq_offset: 2.0,
+        clock_skew_ppm: 50.0,
+        leading_silence: (100, 2000),
+        trailing_silence: 100,
+        snr_db: Some(10.0),
+    };
+
+    let output_1 = ChannelModel::new(channel_config.clone(), 48000, 7).transmit(&signal);
+    let output_2 = ChannelModel::new(channel_config.clone(), 48000, 7).transmit(&signal);
+    let output_3 = ChannelModel::new(channel_config, 48000, 8).transmit(&signal);
+    assert_eq!(output_1, output_2);
+    assert_ne!(output_1, output_3);
+}
+
+#[test]
+fn test_channel_snr() {
+    let signal: Vec<f32> = (0..48000)
+        .map(|x| (2.0 * std::f32::consts::PI * x as f32 / 48000.0 * CARRIER as f32).sin())
+        .collect();
+    let channel_config = ChannelConfig {
+        snr_db: Some(10.0),
+        ..Default::default()
+    };
+    let output = ChannelModel::new(channel_config, 48000, 0).transmit(&signal);
+    let noise: Vec<f32> = output.iter().zip(signal.iter()).map(|(y, x)| y - x).collect();
+    let snr_db = 10.0 * (channel::average_power(&signal) / channel::average_power(&noise)).log10();
+    assert!((snr_db - 10.0).abs() < 0.2, "snr: {}", snr_db);
+}
+
+#[tokio::test]
+async fn test_loopback_channel() {
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let channel_config = ChannelConfig {
+        attenuation: 0.2,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let decoded_data = loopback_through_channel(&data, channel_config, 1).await;
+    assert_eq!(decoded_data, data);
+}
+
+#[tokio::test]
+async fn test_loopback_weak_signal() {
+    // the normalized correlation detects the preamble whatever the volume of the microphone
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let channel_config = ChannelConfig {
+        attenuation: 0.002,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let decoded_data = loopback_through_channel(&data, channel_config, 3).await;
+    assert_eq!(decoded_data, data);
+}
+
+// The frames received at each SNR: the preamble threshold of `Demodulation2::listening` and the
+// bit errors lose more frames at a lower SNR.
+#[tokio::test]
+async fn test_loopback_channel_snr_sweep() {
+    let frame_cnt = 5;
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * frame_cnt);
+    let snrs_db = [20.0, 10.0, 5.0, 0.0, -5.0, -10.0, -15.0];
+    let mut received_frames = vec![];
+    for snr_db in snrs_db {
+        let channel_config = ChannelConfig {
+            attenuation: 0.2,
+            leading_silence: (0, 4800),
+            trailing_silence: 1000,
+            snr_db: Some(snr_db),
+            ..Default::default()
+        };
+        let decoded_data = loopback_through_channel(&data, channel_config, 2).await;
+        received_frames.push(decoded_data.len() / phy_frame::MAX_FRAME_DATA_LENGTH);
+        println!(
+            "[snr sweep] snr: {} dB, received frames: {} / {}",
+            snr_db,
+            received_frames.last().unwrap(),
+            frame_cnt
+        );
+    }
+
+    assert!(
+        received_frames.windows(2).all(|pair| pair[0] >= pair[1]),
+        "{:?} frames at {:?} dB",
+        received_frames,
+        snrs_db
+    );
+    // all the frames down to 0 dB, none at -15 dB
+    assert_eq!(received_frames[..4], [frame_cnt; 4]);
+    assert_eq!(received_frames[6], 0);
+}
+
+#[tokio::test]
+async fn test_decode_wav() {
+    let config = vec![2400, 1200, 2];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 5 + 30);
+    let wav_file = loopback_output_file("decode_wav.wav");
+
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, true);
+    modulator
+        .send_bits_2_file(read_data_2_compressed_u8(data.clone()), data.len() as isize, &wav_file)
+        .await;
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config.clone(),
+        48000,
+        &loopback_output_file("decode_wav_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        true,
+    );
+    let decoded_data = demodulator
+        .decode_wav(&wav_file, phy_frame::FRAME_PAYLOAD_LENGTH, false)
+        .await
+        .unwrap();
+    assert_eq!(decoded_data, data);
+
+    // recorded at 44100 Hz in 16 bits stereo, quieter on the right channel
+    let samples = read_wav_to_array(&wav_file);
+    let samples = channel::resample(&samples, 48000.0 / 44100.0);
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(&wav_file, spec).unwrap();
+    for sample in samples {
+        writer.write_sample((sample * 0.8 * i16::MAX as f32) as i16).unwrap();
+        writer.write_sample((sample * 0.3 * i16::MAX as f32) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let decoded_data = demodulator
+        .decode_wav(&wav_file, phy_frame::FRAME_PAYLOAD_LENGTH, false)
+        .await
+        .unwrap();
+    assert_eq!(decoded_data, data);
+
+    assert!(demodulator
+        .decode_wav(&loopback_output_file("missing.wav"), phy_frame::FRAME_PAYLOAD_LENGTH, false)
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_ofdm_symbols() {
+    use crate::acoustic_modem::demodulation::SymbolDemodulator;
+    use crate::acoustic_modem::ofdm::{OfdmConfig, OfdmDemodulator, OfdmModulator};
+
+    let ofdm_config = OfdmConfig::new(&vec![2400, 1000, 4], 48000).unwrap();
+    assert_eq!(ofdm_config.fft_size, 240);
+    assert_eq!(ofdm_config.data_bins, vec![12, 17, 22, 27]);
+    assert_eq!(ofdm_config.bin_freq(ofdm_config.data_bins[1]), 3400.0);
+    assert_eq!(ofdm_config.pilot_bins, vec![10, 29]);
+    // no carriers, a bin spacing of 200 Hz at 44.1 kHz, a pilot above 24 kHz, a carrier at 0 Hz, a
+    // pilot at 0 Hz
+    for (carriers, sample_rate) in [
+        (vec![2400, 1000, 0], 48000),
+        (vec![2400, 1000, 4], 44100),
+        (vec![20000, 1000, 5], 48000),
+        (vec![0, 1000, 4], 48000),
+        (vec![1000, 1000, 4], 48000),
+    ] {
+        assert!(OfdmConfig::new(&carriers, sample_rate).is_err(), "{:?}", carriers);
+    }
+
+    let bits: Vec<Vec<u8>> = (0..4).map(|_| utils::gen_random_data(100)).collect();
+    let modulator = OfdmModulator::new(ofdm_config.clone());
+    let signal = modulator.modulate(&bits);
+    assert_eq!(signal.len(), 101 * ofdm_config.symbol_len());
+
+    // the echo is shorter than the cyclic prefix
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        echo_taps: vec![(20, 0.6), (45, -0.3)],
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let signal = ChannelModel::new(channel_config, 48000, 3).transmit(&signal);
+
+    let mut demodulator = OfdmDemodulator::new(ofdm_config.clone());
+    demodulator.reset(1.0);
+    let mut received: Vec<Vec<u8>> = vec![vec![]; 4];
+    for window in signal.chunks_exact(ofdm_config.symbol_len()) {
+        let symbol_bits = demodulator.demodulate(window);
+        for i in 0..4 {
+            received[i].extend(symbol_bits[i].iter());
+        }
+    }
+    assert!(demodulator.channel().is_some());
+    assert_eq!(received, bits);
+
+    // the clock skew delays the symbols, their phase turns faster at the higher bins: the pilots at
+    // both edges of the band follow it
+    let channel_config = ChannelConfig {
+        clock_skew_ppm: -200.0,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let signal = ChannelModel::new(channel_config, 48000, 3).transmit(&modulator.modulate(&bits));
+    demodulator.reset(1.0);
+    let mut received: Vec<Vec<u8>> = vec![vec![]; 4];
+    for window in signal.chunks_exact(ofdm_config.symbol_len()) {
+        let symbol_bits = demodulator.demodulate(window);
+        for i in 0..4 {
+            received[i].extend(symbol_bits[i].iter());
+        }
+    }
+    assert_eq!(received, bits);
+}
+
+#[tokio::test]
+async fn test_loopback_ofdm_echo() {
+    let config = vec![2400, 1000, 4];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 9 + 10);
+
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, true);
+    let wave = modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await;
+    let channel_config = ChannelConfig {
+        attenuation: 0.5,
+        echo_taps: vec![(12, 0.5), (40, 0.25)],
+        freq_offset: 0.5,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(15.0),
+        ..Default::default()
+    };
+    let wave = ChannelModel::new(channel_config, 48000, 4).transmit(&wave);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config,
+        48000,
+        &loopback_output_file("ofdm_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        true,
+    );
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(false, phy_frame::FRAME_PAYLOAD_LENGTH, &mut decoded_data, &mut debug_vec, test_data)
+        .await;
+
+    assert_eq!(decoded_data, data);
+}
+
+#[test]
+fn test_frame_codec() {
+    // the default codec is the frame of `PHYFrame::new`
+    let codec = FrameCodec::default();
+    assert_eq!(codec.payload_len(), phy_frame::FRAME_PAYLOAD_LENGTH);
+    assert_eq!(codec.max_data_len(), phy_frame::MAX_FRAME_DATA_LENGTH);
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH);
+    let frame = PHYFrame::new(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+    assert_eq!(
+        utils::read_compressed_u8_2_data(frame.get_whole_frame_bits()),
+        codec.encode(data.len(), read_data_2_compressed_u8(data)).unwrap()
+    );
+
+    // each block corrects its own wrong hexbits, not one more
+    for (code, blocks) in [(RsCode::Short, 1), (RsCode::Medium, 3), (RsCode::Long, 2), (RsCode::Long, 4)] {
+        let codec = FrameCodec::new(code, blocks);
+        let data = utils::gen_random_data(codec.max_data_len() - 5);
+        let mut bits = codec.encode(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+        assert_eq!(bits.len(), codec.payload_len());
+        let codeword_bits = code.codeword_len() * 6;
+        for block in 0..blocks {
+            for hexbit in 0..code.correctable() {
+                bits[block * codeword_bits + hexbit * 6 + 2] ^= 1;
+            }
+        }
+        let (decoded, corrections) = codec.decode_hard(bits.clone()).unwrap();
+        assert_eq!((decoded, corrections), (data, blocks * code.correctable()), "{:?}", codec);
+        bits[codeword_bits - 1] ^= 1;
+        assert!(codec.decode_hard(bits).is_err(), "{:?}", codec);
+    }
+    assert!(FrameCodec::new(RsCode::Long, 2).rate() > FrameCodec::new(RsCode::Short, 2).rate());
+    assert!(FrameCodec::new(RsCode::Short, 1).encode(49, vec![0; 7]).is_err());
+}
+
+#[tokio::test]
+async fn test_loopback_frame_codec() {
+    // larger frames of the long code over OFDM, alone and with the punctured inner code and the
+    // interleaver
+    let config = vec![2400, 1000, 4];
+    for inner in [None, Some(ConvCode::Rate2_3)] {
+        let mut codec = FrameCodec::new(RsCode::Long, 3);
+        codec.inner = inner;
+        codec.interleaver = inner.map(|_| Interleaver::Convolutional { branches: 16, delay: 2 });
+        let data = utils::gen_random_data(codec.max_data_len() * 5 + 30);
+
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, true);
+        modulator.set_frame_codec(Some(codec));
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let channel_config = ChannelConfig {
+            attenuation: 0.5,
+            trailing_silence: 1000,
+            snr_db: Some(20.0),
+            ..Default::default()
+        };
+        let wave = ChannelModel::new(channel_config, 48000, 4).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("frame_codec_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            true,
+        );
+        demodulator.set_frame_codec(Some(codec));
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(false, codec.payload_len(), &mut decoded_data, &mut debug_vec, test_data)
+            .await;
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.is_decoded()), "{:?}", inner);
+        assert_eq!(decoded_data, data);
+    }
+
+    // the same codec on the single carrier modulations
+    let mut codec = FrameCodec::new(RsCode::Long, 2);
+    codec.interleaver = Some(Interleaver::Block { rows: 2 });
+    for (modulation, carrier_cnt) in [
+        (Modulation::Psk, 1),
+        (Modulation::Fsk, 4),
+        (Modulation::Css(css::CSS_DEFAULT_SPREADING_FACTOR), 1),
+    ] {
+        let config = vec![2400, 1000, carrier_cnt];
+        let data = utils::gen_random_data(codec.max_data_len() * 2 + 30);
+
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_modulation(modulation.clone());
+        modulator.set_frame_codec(Some(codec));
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let channel_config = ChannelConfig {
+            attenuation: 0.5,
+            trailing_silence: 1000,
+            snr_db: Some(10.0),
+            ..Default::default()
+        };
+        let wave = ChannelModel::new(channel_config, 48000, 5).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config,
+            48000,
+            &loopback_output_file("frame_codec_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_modulation(modulation.clone());
+        demodulator.set_frame_codec(Some(codec));
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(false, codec.payload_len(), &mut decoded_data, &mut debug_vec, test_data)
+            .await;
+
+        assert_eq!(frames.len(), 3, "{:?}", modulation);
+        for frame in &frames {
+            assert!(frame.is_decoded(), "{:?}", modulation);
+            assert!(frame.carriers[0].rs_corrections.is_some(), "{:?}", modulation);
+        }
+        assert_eq!(decoded_data, data, "{:?}", modulation);
+    }
+}
+
+#[test]
+fn test_convolutional() {
+    for (code, encoded_len, error_spacing) in [(ConvCode::Rate1_2, 212, 10), (ConvCode::Rate2_3, 159, 20)] {
+        let bits = utils::gen_random_data(100);
+        let mut coded = code.encode(&bits);
+        assert_eq!(coded.len(), encoded_len);
+        assert_eq!(code.encoded_len(bits.len()), encoded_len);
+        assert_eq!(code.decode(&soft_decision::hard_llrs(&coded)), bits);
+
+        // isolated wrong bits are corrected
+        for k in (3..coded.len()).step_by(error_spacing) {
+            coded[k] ^= 1;
+        }
+        assert_eq!(code.decode(&soft_decision::hard_llrs(&coded)), bits, "{:?}", code);
+    }
+}
+
+#[test]
+fn test_concatenated_code() {
+    // BPSK over AWGN with about 8% wrong bits: too many wrong hexbits for RS alone, the Viterbi
+    // decoder leaves almost none
+    let noise = rand_distr::Normal::new(0.0, 0.7f32).unwrap();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let rs = FrameCodec::default();
+    let mut concatenated = FrameCodec::default();
+    concatenated.inner = Some(ConvCode::Rate1_2);
+    assert_eq!(concatenated.payload_len(), 2 * (phy_frame::FRAME_PAYLOAD_LENGTH + 6));
+
+    let mut decoded = [0, 0];
+    for _ in 0..20 {
+        let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH);
+        for (k, codec) in [rs, concatenated].iter().enumerate() {
+            let bits = codec.encode(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+            let llrs: Vec<f32> = bits
+                .iter()
+                .map(|&bit| 2.0 * (1.0 - 2.0 * bit as f32 + noise.sample(&mut rng)) / 0.7 / 0.7)
+                .collect();
+            if codec.decode(&llrs).is_ok_and(|(bits, _)| bits == data) {
+                decoded[k] += 1;
+            }
+        }
+    }
+    println!("[test_concatenated_code] decoded frames: {:?}", decoded);
+    assert!(decoded[0] < 10);
+    assert!(decoded[1] >= 19);
+}
+
+#[test]
+fn test_interleaver() {
+    for (interleaver, spread) in [
+        (Interleaver::Block { rows: 8 }, 12),
+        (Interleaver::Convolutional { branches: 8, delay: 3 }, 23),
+    ] {
+        // the delays of the convolutional interleaver wrap around below branches^2 * delay bits
+        for len in [600, 601, 876] {
+            let mut permutation = interleaver.permutation(len);
+            let bits = utils::gen_random_data(len);
+            let interleaved = interleaver.interleave(&bits);
+            assert_eq!(interleaver.deinterleave(&interleaved), bits);
+
+            // a run of sent bits, shorter than the columns, is spread over the frame
+            for run in permutation.windows(7) {
+                for (k, &a) in run.iter().enumerate() {
+                    for &b in &run[k + 1..] {
+                        assert!(a.abs_diff(b) >= spread.min(len / 8), "{:?} {}: {:?}", interleaver, len, run);
+                    }
+                }
+            }
+            permutation.sort();
+            assert_eq!(permutation, (0..len).collect::<Vec<usize>>());
+        }
+    }
+}
+
+#[test]
+fn test_interleaved_burst() {
+    // a burst of 30 wrong bits spoils 6 hexbits of a codeword, one row for each codeword spreads
+    // them over the 3 codewords
+    let mut codec = FrameCodec::new(RsCode::Medium, 3);
+    let data = utils::gen_random_data(codec.max_data_len());
+    for interleaver in [None, Some(Interleaver::Block { rows: 3 })] {
+        codec.interleaver = interleaver;
+        let mut bits = codec.encode(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+        for k in 40..70 {
+            bits[k] ^= 1;
+        }
+        let decoded = codec.decode_hard(bits).ok().map(|(bits, _)| bits);
+        assert_eq!(decoded, interleaver.map(|_| data.clone()), "{:?}", interleaver);
+    }
+
+    // a burst of 64 wrong bits leaves too many wrong bits after the Viterbi decoder for RS, one
+    // row for each bit of the burst isolates them
+    codec.inner = Some(ConvCode::Rate1_2);
+    for interleaver in [None, Some(Interleaver::Block { rows: 64 })] {
+        codec.interleaver = interleaver;
+        let mut bits = codec.encode(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+        for k in 300..364 {
+            bits[k] ^= 1;
+        }
+        let decoded = codec.decode(&soft_decision::hard_llrs(&bits)).ok().map(|(bits, _)| bits);
+        assert_eq!(decoded, interleaver.map(|_| data.clone()), "{:?}", interleaver);
+    }
+}
+
+#[tokio::test]
+async fn test_loopback_interleaved_burst() {
+    // a burst inverts 30 BPSK symbols of a single carrier frame: 6 wrong hexbits in a codeword, 2 in
+    // each of the 3 codewords after the interleaver between the codec and the modulator
+    let config = vec![2400, 1000, 1];
+    let mut codec = FrameCodec::new(RsCode::Medium, 3);
+    let data = utils::gen_random_data(codec.max_data_len());
+    for interleaver in [None, Some(Interleaver::Block { rows: 3 })] {
+        codec.interleaver = interleaver;
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_frame_codec(Some(codec));
+        let mut wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let symbol_len = 48000 / 2400 * modulation::REDUNDANT_PERIODS;
+        let burst_start = phy_frame::gen_preamble(48000).len() + 200 * symbol_len;
+        for sample in wave[burst_start..burst_start + 30 * symbol_len].iter_mut() {
+            *sample = -*sample;
+        }
+        wave.extend(vec![0.0; 1000]);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("interleaved_burst_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_frame_codec(Some(codec));
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(false, codec.payload_len(), &mut decoded_data, &mut debug_vec, test_data)
+            .await;
+
+        assert_eq!(frames.len(), 1, "{:?}", interleaver);
+        assert_eq!(frames[0].is_decoded(), interleaver.is_some(), "{:?}", frames[0]);
+        if interleaver.is_some() {
+            assert_eq!(decoded_data, data);
+        }
+    }
+}
+
+#[test]
+fn test_constellation_map_demap() {
+    for constellation in [
+        Constellation::Bpsk,
+        Constellation::Qpsk,
+        Constellation::Psk8,
+        Constellation::Qam16,
+    ] {
+        let bits = utils::gen_random_data(constellation.bits_per_symbol() * 50);
+        let points = constellation.map_bits(&bits);
+        assert_eq!(points.len(), 50);
+
+        let mut demapped = vec![];
+        for &(i, q) in &points {
+            assert!((i * i + q * q).sqrt() <= 1.0 + 1e-6);
+            // a small disturbance does not change the decision
+            demapped.extend(constellation.demap(i + 0.02, q - 0.02));
+        }
+        assert_eq!(demapped, bits, "{:?}", constellation);
+    }
+
+    // the last symbol is padded with 0
+    assert_eq!(Constellation::Qam16.map_bits(&vec![1, 0, 1, 1, 1]).len(), 2);
+}
+
+#[test]
+fn test_soft_decision() {
+    // the sign of the LLRs is the hard decision of each bit
+    for constellation in [
+        Constellation::Bpsk,
+        Constellation::Qpsk,
+        Constellation::Psk8,
+        Constellation::Qam16,
+    ] {
+        let bits = utils::gen_random_data(constellation.bits_per_symbol() * 50);
+        let mut llrs = vec![];
+        for &(i, q) in &constellation.map_bits(&bits) {
+            llrs.extend(constellation.llrs(i + 0.02, q - 0.02, 0.1));
+        }
+        assert_eq!(soft_decision::hard_bits(&llrs), bits, "{:?}", constellation);
+    }
+
+    // BPSK: 4 * x / noise, a point near the boundary is less reliable
+    let llrs = Constellation::Bpsk.llrs(0.5, 0.3, 0.2);
+    assert!((llrs[0] - 10.0).abs() < 1e-4, "{:?}", llrs);
+    let llrs = Constellation::Bpsk.llrs(-0.05, 0.0, 0.2);
+    assert!((llrs[0] + 1.0).abs() < 1e-4, "{:?}", llrs);
+
+    let bits = vec![0, 1, 1, 0];
+    assert_eq!(soft_decision::hard_bits(&soft_decision::hard_llrs(&bits)), bits);
+    assert_eq!(soft_decision::least_reliable(&[3.0, -0.5, 8.0, 1.0], 2), vec![1, 3]);
+}
+
+#[test]
+fn test_link_metrics_margin() {
+    let decision = |constellation: Constellation, received: (f32, f32)| SymbolDecision {
+        received,
+        decided: constellation.decide(received.0, received.1),
+        half_distance: constellation.half_distance(),
+        phase_error: None,
+        constellation,
+    };
+    let margin = |constellation: Constellation, received: (f32, f32)| {
+        let mut accumulator = CarrierAccumulator::default();
+        accumulator.add(&decision(constellation, received));
+        accumulator.finish(vec![], FrameStatus::Ok, None).margin.unwrap()
+    };
+
+    // 1 on the point, 0 on the boundary, the amplitude of a PSK point is not a margin
+    assert!((margin(Constellation::Bpsk, (1.0, 0.0)) - 1.0).abs() < 1e-4);
+    assert!((margin(Constellation::Bpsk, (-0.25, 0.7)) - 0.25).abs() < 1e-4);
+    assert!((margin(Constellation::Bpsk, (2.0, 0.0)) - 2.0).abs() < 1e-4);
+    assert!(margin(Constellation::Bpsk, (0.0, 0.5)).abs() < 1e-4);
+    let s = std::f32::consts::FRAC_1_SQRT_2;
+    assert!((margin(Constellation::Qpsk, (s, -s)) - 1.0).abs() < 1e-4);
+    assert!((margin(Constellation::Qpsk, (s, 0.1)) - 0.1 / s).abs() < 1e-4);
+    // the inner points of 16-QAM have a boundary on each side
+    let (i, q) = Constellation::Qam16.map(&[1, 1, 1, 1]);
+    assert!((margin(Constellation::Qam16, (i, q)) - 1.0).abs() < 1e-4);
+    for constellation in [Constellation::Psk8, Constellation::Qam16] {
+        let bits = utils::gen_random_data(constellation.bits_per_symbol() * 50);
+        for (i, q) in constellation.map_bits(&bits) {
+            let margin = margin(constellation, (i + 0.05, q - 0.05));
+            assert!(margin > 0.0 && margin.is_finite(), "{:?} {}", constellation, margin);
+        }
+    }
+
+    // a frame without error vector
+    let mut accumulator = CarrierAccumulator::default();
+    accumulator.add(&decision(Constellation::Bpsk, (1.0, 0.0)));
+    let carrier = accumulator.finish(vec![], FrameStatus::Ok, None);
+    assert_eq!(carrier.snr_db, Some(link_metrics::MAX_SNR_DB));
+}
+
+#[test]
+fn test_decode_chase() {
+    // 5 hexbits are wrong, 1 more than RS can correct, the wrong bits are the least reliable
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH);
+    let frame = PHYFrame::new(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+    let bits = utils::read_compressed_u8_2_data(frame.get_whole_frame_bits());
+    assert_eq!(bits.len(), phy_frame::FRAME_PAYLOAD_LENGTH);
+
+    let mut llrs = soft_decision::hard_llrs(&bits);
+    for hexbit in [1, 6, 11, 16, 21] {
+        let index = hexbit * 6 + 2;
+        llrs[index] = if bits[index] == 0 { -0.5 } else { 0.5 };
+    }
+    let hard_llrs = soft_decision::hard_llrs(&soft_decision::hard_bits(&llrs));
+    assert!(FrameCodec::default().decode(&hard_llrs).is_err());
+
+    let (decoded, corrections) = FrameCodec::default().decode(&llrs).unwrap();
+    assert_eq!(decoded, data);
+    assert_eq!(corrections, 4);
+
+    // the hard bits are all as reliable, none of them is flipped
+    let decodings = std::cell::Cell::new(0);
+    let result = soft_decision::chase(
+        &hard_llrs,
+        |_| {
+            decodings.set(decodings.get() + 1);
+            Err::<(), _>(anyhow::Error::msg("RS decoding failed"))
+        },
+        Ok,
+    );
+    assert!(result.is_err());
+    assert_eq!(decodings.get(), 1);
+
+    // the CRC is checked once, on the first combination decoded by RS
+    let checks = std::cell::Cell::new(0);
+    let hard_bits = soft_decision::hard_bits(&llrs);
+    let result = soft_decision::chase(
+        &llrs,
+        |bits| {
+            if bits == hard_bits {
+                Err(anyhow::Error::msg("RS decoding failed"))
+            } else {
+                Ok(())
+            }
+        },
+        |_| {
+            checks.set(checks.get() + 1);
+            Err::<(), _>(anyhow::Error::new(FrameStatus::CrcMismatch))
+        },
+    );
+    assert_eq!(FrameStatus::of_error(&result.unwrap_err()), FrameStatus::CrcMismatch);
+    assert_eq!(checks.get(), 1);
+}
+
+#[test]
+fn test_crc() {
+    // the check values of "123456789"
+    let bits = utils::read_compressed_u8_2_data(b"123456789".to_vec());
+    assert_eq!(crc::crc16(&bits), 0x29B1);
+    assert_eq!(crc::crc32(&bits), 0xFC89_1918);
+    assert_eq!(crc::to_bits(0b1011, 6), vec![0, 0, 1, 0, 1, 1]);
+
+    // any single bit error is detected
+    for i in 0..bits.len() {
+        let mut corrupted = bits.clone();
+        corrupted[i] ^= 1;
+        assert_ne!(crc::crc16(&corrupted), crc::crc16(&bits));
+        assert_ne!(crc::crc32(&corrupted), crc::crc32(&bits));
+    }
+}
+
+#[test]
+fn test_frame_crc() {
+    // both frame variants carry their CRC, a corrupted frame is reported and not decoded
+    for length in [phy_frame::MAX_FRAME_DATA_LENGTH, 20, 300] {
+        let data = utils::gen_random_data(length);
+        let (_, payload) =
+            PHYFrame::new_no_encoding(length, read_data_2_compressed_u8(data.clone()));
+        let mut bits = utils::read_compressed_u8_2_data(payload);
+        let frame_len = phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING + length + phy_frame::crc_length(length);
+        assert!(bits.len() >= frame_len.max(phy_frame::FRAME_LENGTH_NO_ENCODING));
+        bits.truncate(frame_len.max(phy_frame::FRAME_LENGTH_NO_ENCODING));
+        let llrs = soft_decision::hard_llrs(&bits);
+        assert_eq!(demodulation::decode_no_encoding(&llrs).unwrap(), data);
+
+        bits[phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING + length / 2] ^= 1;
+        let error = demodulation::decode_no_encoding(&soft_decision::hard_llrs(&bits)).unwrap_err();
+        assert_eq!(FrameStatus::of_error(&error), FrameStatus::CrcMismatch, "{}", length);
+    }
+    // too short for the length field, e.g. `listening` with a `data_len` below 16 bits
+    let error = demodulation::decode_no_encoding(&soft_decision::hard_llrs(&[0; 10])).unwrap_err();
+    assert_eq!(FrameStatus::of_error(&error), FrameStatus::Undecodable);
+
+    let data = utils::gen_random_data(50);
+    let frame = PHYFrame::new(data.len(), read_data_2_compressed_u8(data.clone())).unwrap();
+    let mut bits = utils::read_compressed_u8_2_data(frame.get_whole_frame_bits());
+    // RS corrects 2 wrong hexbits, not 8
+    bits[3] ^= 1;
+    bits[100] ^= 1;
+    let (decoded, corrections) =
+        FrameCodec::default().decode(&soft_decision::hard_llrs(&bits)).unwrap();
+    assert_eq!((decoded, corrections), (data, 2));
+    for hexbit in 0..8 {
+        bits[hexbit * 18 + 1] ^= 1;
+    }
+    let error = FrameCodec::default().decode(&soft_decision::hard_llrs(&bits)).unwrap_err();
+    assert_ne!(FrameStatus::of_error(&error), FrameStatus::Ok);
+}
+
+#[test]
+fn test_frame_header() {
+    // the header goes through both frame variants before the data
+    let mut header = FrameHeader::new(FrameType::Nack, 200, 3, phy_frame::BROADCAST_ADDRESS);
+    header.flags = phy_frame::FLAG_ACK_REQUEST | phy_frame::FLAG_RETRANSMISSION;
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH - phy_frame::FRAME_HEADER_LENGTH);
+    let (length, payload) =
+        PHYFrame::serialize_header(&header, data.len(), read_data_2_compressed_u8(data.clone()));
+    assert_eq!(length, phy_frame::MAX_FRAME_DATA_LENGTH);
+
+    let frame = PHYFrame::new(length, payload.clone()).unwrap();
+    let bits = utils::read_compressed_u8_2_data(frame.get_whole_frame_bits());
+    let (decoded, _) = FrameCodec::default().decode(&soft_decision::hard_llrs(&bits)).unwrap();
+    assert_eq!(PHYFrame::parse_header(&decoded).unwrap(), (header, data.clone()));
+
+    let (_, frame) = PHYFrame::new_no_encoding(length, payload);
+    let bits = utils::read_compressed_u8_2_data(frame);
+    let decoded = demodulation::decode_no_encoding(&soft_decision::hard_llrs(&bits)).unwrap();
+    let (parsed, parsed_data) = PHYFrame::parse_header(&decoded).unwrap();
+    assert_eq!((parsed, parsed_data), (header, data));
+    assert!(parsed.has_flag(phy_frame::FLAG_RETRANSMISSION));
+    assert!(!parsed.has_flag(phy_frame::FLAG_MORE_FRAGMENTS));
+    assert!(parsed.is_for(7));
+
+    let header = FrameHeader::new(FrameType::Data, 0, 1, 2);
+    assert!(header.is_for(2) && !header.is_for(1));
+    // another version, or too short for a header
+    let mut bytes = header.to_bytes();
+    bytes[0] ^= 0b1100_0000;
+    assert!(FrameHeader::from_bytes(&bytes).is_err());
+    assert!(PHYFrame::parse_header(&[0; 20]).is_err());
+    // a header and more data than fits in the frame of `new`, only `new_no_encoding` takes it
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH);
+    let (length, payload) =
+        PHYFrame::serialize_header(&header, data.len(), read_data_2_compressed_u8(data.clone()));
+    assert!(PHYFrame::new(length, payload.clone()).is_err());
+    let (_, frame) = PHYFrame::new_no_encoding(length, payload);
+    let bits = utils::read_compressed_u8_2_data(frame);
+    let decoded = demodulation::decode_no_encoding(&soft_decision::hard_llrs(&bits)).unwrap();
+    assert_eq!(PHYFrame::parse_header(&decoded).unwrap(), (header, data));
+}
+
+#[tokio::test]
+async fn test_loopback_crc_mismatch() {
+    // a frame corrupted before the modulation is received without errors of the channel, but its
+    // CRC does not match: it is reported and its bits are dropped
+    let config = vec![CARRIER, 6000, 1];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH);
+    let modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    let mut wave = vec![];
+    for corrupt in [false, true] {
+        let (_, payload) =
+            PHYFrame::new_no_encoding(data.len(), read_data_2_compressed_u8(data.clone()));
+        let mut bits = utils::read_compressed_u8_2_data(payload);
+        if corrupt {
+            bits[30] ^= 1;
+        }
+        wave.extend(phy_frame::gen_preamble(48000));
+        wave.extend(modulator.modulate(&bits, 0));
+        wave.extend(vec![0.0; 480]);
+    }
+    let channel_config = ChannelConfig {
+        attenuation: 0.5,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(30.0),
+        ..Default::default()
+    };
+    let wave = ChannelModel::new(channel_config, 48000, 24).transmit(&wave);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config.clone(),
+        48000,
+        &loopback_output_file("crc_mismatch_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        false,
+    );
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    let frames = demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+    let status: Vec<FrameStatus> = frames.iter().map(|frame| frame.carriers[0].status).collect();
+    assert_eq!(status, vec![FrameStatus::Ok, FrameStatus::CrcMismatch]);
+    assert_eq!(decoded_data, data);
+}
+
+#[tokio::test]
+async fn test_loopback_long_frame() {
+    // a frame of several thousand bits carries a CRC-32, its symbols slip by about one symbol at
+    // the end of the frame without the timing recovery
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        clock_skew_ppm: 300.0,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+
+    for constellation in [Constellation::Bpsk, Constellation::Qpsk] {
+        let data = utils::gen_random_data(3000);
+        let (_, payload) =
+            PHYFrame::new_no_encoding(data.len(), read_data_2_compressed_u8(data.clone()));
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(constellation);
+        let mut wave = phy_frame::gen_preamble(48000);
+        wave.extend(modulator.modulate(&utils::read_compressed_u8_2_data(payload), 0));
+        let wave = ChannelModel::new(channel_config.clone(), 48000, 14).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("long_frame_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(constellation);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frame_len = phy_frame::FRAME_LENGTH_LENGTH_NO_ENCODING
+            + data.len()
+            + phy_frame::crc_length(data.len());
+        demodulator
+            .listening(false, frame_len, &mut decoded_data, &mut debug_vec, test_data)
+            .await;
+
+        assert_eq!(decoded_data, data, "{:?}", constellation);
+    }
+}
+
+#[tokio::test]
+async fn test_loopback_constellations() {
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(30.0),
+        ..Default::default()
+    };
+
+    for constellation in [Constellation::Qpsk, Constellation::Psk8, Constellation::Qam16] {
+        for (config, enable_ofdm, frame_len) in [
+            (
+                vec![CARRIER, 6000, 1],
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+            ),
+            (vec![2400, 1000, 4], true, phy_frame::FRAME_PAYLOAD_LENGTH),
+        ] {
+            let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+            let mut modulator = Modulator::new_loopback(config.clone(), 48000, enable_ofdm);
+            modulator.set_constellation(constellation);
+            let wave = modulator
+                .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+                .await;
+            let wave = ChannelModel::new(channel_config.clone(), 48000, 5).transmit(&wave);
+
+            let mut demodulator = Demodulation2::new_loopback(
+                config,
+                48000,
+                &loopback_output_file("constellation_output.txt"),
+                modulation::REDUNDANT_PERIODS,
+                enable_ofdm,
+            );
+            demodulator.set_constellation(constellation);
+            let mut decoded_data = vec![];
+            let mut debug_vec = vec![];
+            let test_data = loopback_chunks(&wave);
+            demodulator
+                .listening(false, frame_len, &mut decoded_data, &mut debug_vec, test_data)
+                .await;
+
+            assert_eq!(decoded_data, data, "{:?}, ofdm: {}", constellation, enable_ofdm);
+        }
+    }
+}
+
+#[test]
+fn test_differential_detector() {
+    for constellation in [Constellation::Dbpsk, Constellation::Dqpsk] {
+        let bits = utils::gen_random_data(constellation.bits_per_symbol() * 50);
+        let points = constellation.map_bits(&bits);
+        // the reference symbol
+        assert_eq!(points.len(), 51);
+
+        // an arbitrary carrier phase and gain does not change the bits
+        let (sin, cos) = 2.0_f32.sin_cos();
+        let mut detector = DifferentialDetector::default();
+        let mut detected = vec![];
+        for &(i, q) in &points {
+            detected.extend(detector.detect(constellation, 0.3 * (i * cos - q * sin), 0.3 * (i * sin + q * cos)));
+        }
+        assert_eq!(detected, bits, "{:?}", constellation);
+    }
+}
+
+#[tokio::test]
+async fn test_loopback_differential() {
+    // the carrier phase drifts during the frame
+    let channel_config = ChannelConfig {
+        attenuation: 0.5,
+        freq_offset: 5.0,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+
+    for constellation in [Constellation::Dbpsk, Constellation::Dqpsk] {
+        let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(constellation);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let wave = ChannelModel::new(channel_config.clone(), 48000, 6).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("differential_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(constellation);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+
+        assert_eq!(decoded_data, data, "{:?}", constellation);
+    }
+}
+
+#[test]
+fn test_fsk_symbols() {
+    use crate::acoustic_modem::demodulation::SymbolDemodulator;
+    use crate::acoustic_modem::fsk::{self, FskConfig, FskDemodulator};
+
+    // the tone of 1 cycle per symbol is much stronger than the others
+    let tone: Vec<f32> = (0..96)
+        .map(|t| (2.0 * std::f32::consts::PI * 3400.0 * t as f32 / 48000.0).sin())
+        .collect();
+    assert!(fsk::goertzel(&tone, 3400.0, 48000) > 100.0 * fsk::goertzel(&tone, 2400.0, 48000));
+
+    // 6 carriers: only the first 4 of them are used
+    let fsk_config = FskConfig::new(&vec![2400, 1000, 6], 48000, modulation::REDUNDANT_PERIODS);
+    assert_eq!(fsk_config.tones, vec![2400, 3400, 4400, 5400]);
+    assert_eq!(fsk_config.bits_per_symbol(), 2);
+
+    let bits = utils::gen_random_data(100);
+    let signal = fsk::modulate(&fsk_config, &bits);
+    assert_eq!(signal.len(), 50 * fsk_config.symbol_len);
+
+    let mut demodulator = FskDemodulator::new(fsk_config.clone());
+    let mut received: Vec<u8> = vec![];
+    for window in signal.chunks_exact(fsk_config.symbol_len) {
+        received.extend(demodulator.demodulate(window)[0].iter());
+    }
+    assert_eq!(received, bits);
+}
+
+#[tokio::test]
+async fn test_loopback_fsk() {
+    // the echo distorts the phase response of the channel
+    let channel_config = ChannelConfig {
+        attenuation: 0.5,
+        echo_taps: vec![(7, 0.4), (23, -0.3)],
+        freq_offset: 5.0,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(5.0),
+        ..Default::default()
+    };
+
+    for carrier_cnt in [2, 4] {
+        let config = vec![2400, 1000, carrier_cnt];
+        let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_modulation(Modulation::Fsk);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let wave = ChannelModel::new(channel_config.clone(), 48000, 7).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config,
+            48000,
+            &loopback_output_file("fsk_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_modulation(Modulation::Fsk);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+
+        assert_eq!(decoded_data, data, "{} tones", carrier_cnt);
+    }
+}
+
+#[test]
+fn test_css_symbols() {
+    use crate::acoustic_modem::css::{self, CssConfig, CssDemodulator};
+    use crate::acoustic_modem::demodulation::SymbolDemodulator;
+
+    let css_config = CssConfig::new(48000, 7);
+    assert_eq!(css_config.chip_cnt(), 128);
+    assert_eq!(css_config.symbol_len(), 768);
+
+    let bits = utils::gen_random_data(7 * 40);
+    let signal = css::modulate(&css_config, &bits);
+    // with the reference symbol
+    assert_eq!(signal.len(), 41 * css_config.symbol_len());
+
+    // below the noise
+    let channel_config = ChannelConfig {
+        attenuation: 0.1,
+        snr_db: Some(-3.0),
+        ..Default::default()
+    };
+    let mut signal = ChannelModel::new(channel_config, 48000, 8).transmit(&signal);
+    signal.extend(vec![0.0; 2 * css::CSS_CHIP_LEN]);
+
+    // a timing error of 2 chips is corrected by the reference symbol
+    let mut demodulator = CssDemodulator::new(css_config.clone());
+    demodulator.reset(1.0);
+    let mut received: Vec<u8> = vec![];
+    for window in signal[2 * css::CSS_CHIP_LEN..].chunks_exact(css_config.symbol_len()) {
+        received.extend(demodulator.demodulate(window)[0].iter());
+    }
+    assert_eq!(received, bits);
+}
+
+#[tokio::test]
+async fn test_loopback_css() {
+    let channel_config = ChannelConfig {
+        attenuation: 0.2,
+        echo_taps: vec![(30, 0.3)],
+        freq_offset: 3.0,
+        trailing_silence: 1000,
+        // the PSK link loses frames here
+        snr_db: Some(-3.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    modulator.set_modulation(Modulation::Css(css::CSS_DEFAULT_SPREADING_FACTOR));
+    let wave = modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await;
+    let wave = ChannelModel::new(channel_config, 48000, 9).transmit(&wave);
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config,
+        48000,
+        &loopback_output_file("css_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        false,
+    );
+    demodulator.set_modulation(Modulation::Css(css::CSS_DEFAULT_SPREADING_FACTOR));
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+
+    assert_eq!(decoded_data, data);
+}
+
+// periodic correlation of two codes of the same length at the shift, as +1 / -1
+fn periodic_correlation(code_1: &Vec<u8>, code_2: &Vec<u8>, shift: usize) -> i32 {
+    let len = code_1.len();
+    (0..len)
+        .map(|i| if code_1[i] == code_2[(i + shift) % len] { 1 } else { -1 })
+        .sum()
+}
+
+#[test]
+fn test_pn_codes() {
+    for degree in 3..=10 {
+        let code = PnCode::MSequence(degree).chips();
+        assert_eq!(code.len(), (1 << degree) - 1);
+        for shift in 1..code.len() {
+            assert_eq!(periodic_correlation(&code, &code, shift), -1, "degree {}", degree);
+        }
+    }
+
+    // the cross correlation of the Gold codes takes 3 values: -1, -t, t - 2
+    for (degree, t) in [(5, 9), (6, 17), (7, 17), (9, 33), (10, 65)] {
+        let code_1 = PnCode::Gold(degree, 0).chips();
+        let code_2 = PnCode::Gold(degree, 1).chips();
+        for shift in 0..code_1.len() {
+            let correlation = periodic_correlation(&code_1, &code_2, shift);
+            assert!([-1, -t, t - 2].contains(&correlation), "degree {}: {}", degree, correlation);
+        }
+    }
+
+    // the aperiodic sidelobes of the Barker codes are at most 1
+    for len in [2, 3, 4, 5, 7, 11, 13] {
+        let code = PnCode::Barker(len).chips();
+        for shift in 1..len {
+            let sidelobe: i32 = (0..len - shift)
+                .map(|i| if code[i] == code[i + shift] { 1 } else { -1 })
+                .sum();
+            assert!(sidelobe.abs() <= 1, "length {}", len);
+        }
+    }
+}
+
+async fn dsss_wave(config: &Vec<u32>, pn_code: &PnCode, data: &Vec<u8>) -> Vec<f32> {
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    modulator.set_redundant_periods(1);
+    modulator.set_modulation(Modulation::Dsss(pn_code.clone()));
+    modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await
+}
+
+async fn dsss_receive(config: &Vec<u32>, pn_code: &PnCode, wave: Vec<f32>, name: &str) -> Vec<u8> {
+    let mut demodulator = Demodulation2::new_loopback(
+        config.clone(),
+        48000,
+        &loopback_output_file(name),
+        1,
+        false,
+    );
+    demodulator.set_modulation(Modulation::Dsss(pn_code.clone()));
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+    decoded_data
+}
+
+#[tokio::test]
+async fn test_loopback_dsss() {
+    let channel_config = ChannelConfig {
+        attenuation: 0.2,
+        echo_taps: vec![(30, 0.3)],
+        trailing_silence: 1000,
+        // the processing gain of 31 chips is about 15dB
+        snr_db: Some(0.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+    let pn_code = PnCode::Gold(5, 0);
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+
+    let wave = dsss_wave(&config, &pn_code, &data).await;
+    let wave = ChannelModel::new(channel_config, 48000, 10).transmit(&wave);
+    let decoded_data = dsss_receive(&config, &pn_code, wave, "dsss_output.txt").await;
+
+    assert_eq!(decoded_data, data);
+}
+
+// two links on the same carrier, separated by the Gold codes of one family
+#[tokio::test]
+async fn test_loopback_dsss_shared_carrier() {
+    let channel_config = ChannelConfig {
+        attenuation: 0.2,
+        trailing_silence: 1000,
+        snr_db: Some(10.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+    let (pn_code_1, pn_code_2) = (PnCode::Gold(5, 0), PnCode::Gold(5, 1));
+    let data_1 = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let data_2 = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+
+    // the senders are aligned, so that the receivers share the preamble
+    let wave_1 = dsss_wave(&config, &pn_code_1, &data_1).await;
+    let wave_2 = dsss_wave(&config, &pn_code_2, &data_2).await;
+    let wave: Vec<f32> = wave_1
+        .iter()
+        .zip(wave_2.iter())
+        .map(|(x_1, x_2)| (x_1 + x_2) / 2.0)
+        .collect();
+    let wave = ChannelModel::new(channel_config, 48000, 11).transmit(&wave);
+
+    let decoded_1 = dsss_receive(&config, &pn_code_1, wave.clone(), "dsss_output_1.txt").await;
+    let decoded_2 = dsss_receive(&config, &pn_code_2, wave, "dsss_output_2.txt").await;
+    assert_eq!(decoded_1, data_1);
+    assert_eq!(decoded_2, data_2);
+}
+
+// fraction of the energy of the signal more than `bandwidth` away from `freq`
+fn out_of_band_energy(signal: &Vec<f32>, freq: f32, bandwidth: f32, sample_rate: u32) -> f32 {
+    use rustfft::num_complex::Complex;
+
+    let mut buffer: Vec<Complex<f32>> = signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+    rustfft::FftPlanner::new()
+        .plan_fft_forward(buffer.len())
+        .process(&mut buffer);
+    let bin_freq = sample_rate as f32 / buffer.len() as f32;
+    let (mut total, mut out_of_band) = (0.0, 0.0);
+    for (k, value) in buffer[..buffer.len() / 2].iter().enumerate() {
+        total += value.norm_sqr();
+        if (k as f32 * bin_freq - freq).abs() > bandwidth {
+            out_of_band += value.norm_sqr();
+        }
+    }
+    out_of_band / total
+}
+
+#[test]
+fn test_pulse_shape() {
+    assert_eq!(PulseShape::Rectangular.window(80), vec![1.0; 80]);
+    assert_eq!(PulseShape::RaisedCosine(0.0).window(80), vec![1.0; 80]);
+    let window = PulseShape::RaisedCosine(0.5).window(80);
+    assert_eq!(window[20..60], vec![1.0; 40]);
+    // the edges of the neighbouring symbols add up to 1
+    let hann = PulseShape::Hann.window(80);
+    for n in 0..80 {
+        assert!((hann[n] - hann[79 - n]).abs() < 1e-6);
+        assert!((hann[n] + hann[(n + 40) % 80] - 1.0).abs() < 1e-6);
+    }
+
+    // the shaped symbols do not splatter over the band
+    let bits = utils::gen_random_data(2000);
+    let mut modulator = Modulator::new_loopback(vec![CARRIER, 6000, 1], 48000, false);
+    let rectangular = out_of_band_energy(&modulator.modulate(&bits, 0), CARRIER as f32, 4000.0, 48000);
+    for pulse_shape in [PulseShape::RaisedCosine(0.5), PulseShape::Hann] {
+        modulator.set_pulse_shape(pulse_shape);
+        let shaped = out_of_band_energy(&modulator.modulate(&bits, 0), CARRIER as f32, 4000.0, 48000);
+        assert!(shaped < rectangular / 10.0, "{:?}", pulse_shape);
+    }
+}
+
+#[tokio::test]
+async fn test_loopback_pulse_shaping() {
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        echo_taps: vec![(10, 0.2)],
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+
+    for pulse_shape in [PulseShape::RaisedCosine(0.5), PulseShape::Hann] {
+        for (config, enable_ofdm, frame_len) in [
+            (
+                vec![CARRIER, 6000, 1],
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+            ),
+            (vec![2400, 1000, 4], true, phy_frame::FRAME_PAYLOAD_LENGTH),
+        ] {
+            let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+            let mut modulator = Modulator::new_loopback(config.clone(), 48000, enable_ofdm);
+            modulator.set_constellation(Constellation::Qpsk);
+            modulator.set_pulse_shape(pulse_shape);
+            let wave = modulator
+                .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+                .await;
+            let wave = ChannelModel::new(channel_config.clone(), 48000, 12).transmit(&wave);
+
+            let mut demodulator = Demodulation2::new_loopback(
+                config,
+                48000,
+                &loopback_output_file("pulse_shaping_output.txt"),
+                modulation::REDUNDANT_PERIODS,
+                enable_ofdm,
+            );
+            demodulator.set_constellation(Constellation::Qpsk);
+            demodulator.set_pulse_shape(pulse_shape);
+            let mut decoded_data = vec![];
+            let mut debug_vec = vec![];
+            let test_data = loopback_chunks(&wave);
+            demodulator
+                .listening(false, frame_len, &mut decoded_data, &mut debug_vec, test_data)
+                .await;
+
+            assert_eq!(decoded_data, data, "{:?}, ofdm: {}", pulse_shape, enable_ofdm);
+        }
+    }
+}
+
+#[test]
+fn test_costas_loop() {
+    use crate::acoustic_modem::carrier_recovery::CostasLoop;
+
+    // QPSK at 600 symbols per second, 3Hz frequency offset and 0.3 rad phase offset
+    let (symbol_rate, freq_offset) = (600.0, 3.0);
+    let bits = utils::gen_random_data(2 * 400);
+    let points = Constellation::Qpsk.map_bits(&bits);
+    let mut carrier_loop = CostasLoop::new(symbol_rate);
+    let mut detected = vec![];
+    for (n, &(i, q)) in points.iter().enumerate() {
+        let phase = 0.3 + 2.0 * std::f32::consts::PI * freq_offset * n as f32 / symbol_rate;
+        let (sin, cos) = phase.sin_cos();
+        let (i, q) = carrier_loop.track(Constellation::Qpsk, i * cos - q * sin, i * sin + q * cos);
+        detected.extend(Constellation::Qpsk.demap(i, q));
+    }
+
+    assert_eq!(detected, bits);
+    let state = carrier_loop.state();
+    assert!((state.freq_offset - freq_offset).abs() < 0.1, "{:?}", state);
+    assert!(state.phase_error.abs() < 0.05, "{:?}", state);
+}
+
+#[tokio::test]
+async fn test_loopback_carrier_tracking() {
+    // the phase drifts by about 1 rad within a frame, 8-PSK is lost without the carrier recovery
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        clock_skew_ppm: 1000.0,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let config = vec![CARRIER, 6000, 1];
+
+    for constellation in [Constellation::Qpsk, Constellation::Psk8] {
+        let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(constellation);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let wave = ChannelModel::new(channel_config.clone(), 48000, 13).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("carrier_tracking_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(constellation);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+
+        assert_eq!(decoded_data, data, "{:?}", constellation);
+        let tracking = demodulator.carrier_tracking();
+        assert_eq!(tracking.len(), 1);
+        // the carrier of the faster sender clock is 1.2Hz higher, the timing recovery follows the
+        // symbols and takes most of the drift of the phase
+        assert!(tracking[0].freq_offset.abs() < 2.0, "{:?}", tracking);
+    }
+}
+
+#[test]
+fn test_fractional_window() {
+    use crate::acoustic_modem::timing_recovery;
+
+    let omega = 2.0 * std::f32::consts::PI * 1200.0 / 48000.0;
+    let signal: Vec<f32> = (0..200).map(|t| (omega * t as f32).sin()).collect();
+    for fraction in [0.0, 0.25, 0.5, 0.9] {
+        let window = timing_recovery::fractional_window(&signal, 50, fraction, 80);
+        for (n, &x) in window.iter().enumerate() {
+            let expected = (omega * (50.0 + n as f32 + fraction)).sin();
+            assert!((x - expected).abs() < 1e-3, "fraction {}: {} against {}", fraction, x, expected);
+        }
+    }
+}
+
+#[test]
+fn test_timing_error() {
+    use crate::acoustic_modem::demodulation::{PskDemodulator, SymbolDemodulator};
+    use crate::acoustic_modem::front_end::{FrontEnd, FrontEndConfig};
+    use rand::{Rng, SeedableRng};
+
+    // each carrier has its own references of the window between two symbols
+    let carrier_freq = vec![CARRIER, 2 * CARRIER];
+    let symbol_len = (48000 / CARRIER) as usize * modulation::REDUNDANT_PERIODS;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+    let mut sign = || if rng.gen::<bool>() { 1.0 } else { -1.0 };
+    let symbols: Vec<Vec<(f32, f32)>> = (0..1000)
+        .map(|_| carrier_freq.iter().map(|_| (sign(), sign())).collect())
+        .collect();
+    // the QPSK symbols of all the carriers, at the time `t` in samples
+    let signal = |t: f32| -> f32 {
+        let k = (t / symbol_len as f32).floor();
+        if k < 0.0 || k as usize >= symbols.len() {
+            return 0.0;
+        }
+        carrier_freq
+            .iter()
+            .zip(&symbols[k as usize])
+            .map(|(&carrier, &(i_value, q_value))| {
+                let phase = 2.0 * std::f32::consts::PI * carrier as f32 * t / 48000.0;
+                i_value * phase.sin() + q_value * phase.cos()
+            })
+            .sum()
+    };
+
+    for late in [-6.0, -2.5, 0.0, 1.5, 4.0] {
+        let mut demodulator = PskDemodulator::new(
+            &carrier_freq,
+            48000,
+            symbol_len,
+            Constellation::Qpsk,
+            PulseShape::Rectangular,
+            &FrontEnd::new(&FrontEndConfig::bypass(), 48000),
+            None,
+        );
+        let mut errors = vec![];
+        for k in 0..symbols.len() {
+            let start = (k * symbol_len) as f32 + late;
+            let window: Vec<f32> = (0..symbol_len).map(|n| signal(start + n as f32)).collect();
+            let mid_window: Vec<f32> = (0..symbol_len)
+                .map(|n| signal(start - (symbol_len / 2) as f32 + n as f32))
+                .collect();
+            demodulator.demodulate(&window);
+            errors.extend(demodulator.timing_error(&mid_window));
+        }
+        assert!(errors.len() > symbols.len() / 2, "{} symbols with a timing error", errors.len());
+        if late == 0.0 {
+            // the mid window is the one expected from the two symbols around it
+            assert!(errors.iter().all(|error| error.abs() < 0.1), "{:?}", errors);
+            continue;
+        }
+        // the slope of the detector is below 1 with a neighbour symbol in the window
+        let error = errors.iter().sum::<f32>() / errors.len() as f32;
+        let message = format!("sampled {} samples late, timing error {}", late, error);
+        assert!(error * late > 0.0, "{}", message);
+        assert!(error.abs() > late.abs() / 5.0 && error.abs() < late.abs(), "{}", message);
+    }
+}
+
+#[test]
+fn test_preamble_detector() {
+    use crate::acoustic_modem::preamble_detector::{self, PreambleDetector};
+    use crate::acoustic_modem::timing_recovery;
+
+    let preamble = phy_frame::gen_preamble(48000);
+    let detector = PreambleDetector::new(&preamble, 0.3);
+    let noise: Vec<f32> = (0..3000)
+        .map(|t| 0.1 * ((t * 7919 % 1000) as f32 / 500.0 - 1.0))
+        .collect();
+
+    // the preamble delayed by 1000 + `delay` samples, the gain does not change the metric
+    for (gain, delay) in [(1.0, 0.0), (0.01, 0.5), (-0.2, 0.25)] {
+        let delayed =
+            timing_recovery::fractional_window(&preamble, -1, 1.0 - delay, preamble.len() + 1);
+        let mut samples: Vec<f32> = noise.iter().map(|x| x * gain).collect();
+        for (n, x) in delayed.iter().enumerate() {
+            samples[1000 + n] += x * gain;
+        }
+
+        let correlation = detector.correlate(&samples);
+        let metric = detector.normalize(&samples, &correlation);
+        let peak = (0..metric.len())
+            .max_by(|&a, &b| metric[a].total_cmp(&metric[b]))
+            .unwrap();
+        assert!(metric[peak] > 0.9, "gain {}: metric {}", gain, metric[peak]);
+        assert!(metric[..300].iter().all(|&x| x < detector.threshold()));
+        let offset = preamble_detector::interpolate_peak(&correlation, peak);
+        let position = peak as f32 + offset;
+        assert!(
+            (position - 1000.0 - delay).abs() < 0.2,
+            "delay {}: peak at {}",
+            delay,
+            position
+        );
+    }
+}
+
+#[test]
+fn test_preamble_families() {
+    use crate::acoustic_modem::preamble::{
+        BarkerPreamble, ChirpPreamble, MSequencePreamble, Preamble, ZadoffChuPreamble,
+    };
+    use crate::acoustic_modem::preamble_detector::PreambleDetector;
+
+    assert_eq!(ChirpPreamble::default().waveform(48000), phy_frame::gen_preamble(48000));
+
+    let preambles: Vec<Box<dyn Preamble>> = vec![
+        Box::new(ChirpPreamble {
+            start: 2000.0,
+            end: 6000.0,
+            half_len: 400,
+        }),
+        Box::new(BarkerPreamble {
+            len: 13,
+            carrier: 3000.0,
+            chip_len: 24,
+        }),
+        Box::new(MSequencePreamble {
+            degree: 6,
+            carrier: 4000.0,
+            chip_len: 12,
+        }),
+        Box::new(ZadoffChuPreamble {
+            root: 25,
+            len: 63,
+            carrier: 4000.0,
+            chip_len: 12,
+        }),
+    ];
+    for preamble in preambles {
+        let waveform = preamble.waveform(48000);
+        assert!(waveform.iter().all(|x| x.abs() <= 1.0));
+        let (low, high) = preamble.band(48000);
+        assert!(0.0 <= low && low < high && high < 24000.0);
+
+        // a sharp autocorrelation peak: the metric falls far from it
+        let mut samples = vec![0.0; 500];
+        samples.extend(waveform.iter());
+        samples.extend(vec![0.0; 500]);
+        let detector = PreambleDetector::new(&waveform, 0.3);
+        let metric = detector.normalize(&samples, &detector.correlate(&samples));
+        assert!(metric[500] > 0.99, "{:?}: peak {}", preamble, metric[500]);
+        let sidelobe = metric
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i.abs_diff(500) > 48)
+            .map(|(_, &x)| x)
+            .fold(0.0, f32::max);
+        assert!(sidelobe < 0.5, "{:?}: sidelobe {}", preamble, sidelobe);
+    }
+}
+
+#[test]
+fn test_front_end() {
+    use crate::acoustic_modem::front_end::{FrontEnd, FrontEndConfig};
+
+    let mut config = FrontEndConfig::new((1000.0, 3000.0), 48000);
+    config.notches.push((6000.0, 5.0));
+    let front_end = FrontEnd::new(&config, 48000);
+    let tone = |freq: f32, amplitude: f32| -> Vec<f32> {
+        (0..9600)
+            .map(|t| amplitude * (2.0 * std::f32::consts::PI * freq * t as f32 / 48000.0).sin())
+            .collect()
+    };
+    // the amplitude after the transient of the filters
+    let amplitude = |samples: &Vec<f32>| samples[4800..].iter().fold(0.0f32, |m, x| m.max(x.abs()));
+
+    // the carriers pass, the DC offset, the hum and the notched tone do not
+    let signal = tone(2000.0, 1.0);
+    let (gain, _) = front_end.response(2000.0);
+    assert!((amplitude(&front_end.process(&signal)) - gain).abs() < 0.01);
+    assert!(gain > 0.9);
+    let dc = vec![0.5; 9600];
+    assert!(amplitude(&front_end.process(&dc)) < 1e-3);
+    assert!(amplitude(&front_end.process(&tone(50.0, 0.5))) < 0.05);
+    assert!(amplitude(&front_end.process(&tone(6000.0, 1.0))) < 0.01);
+    assert!(front_end.group_delay(2000.0) > 0.0);
+
+    let bypass = FrontEnd::new(&FrontEndConfig::bypass(), 48000);
+    assert_eq!(bypass.process(&signal), signal);
+}
+
+#[tokio::test]
+async fn test_loopback_front_end() {
+    // DC offset and mains hum of the sound card, and a tone next to the band of the signal
+    let config = vec![CARRIER, 6000, 1];
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 3);
+    let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+    modulator.set_constellation(Constellation::Qpsk);
+    let wave = modulator
+        .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+        .await;
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(20.0),
+        ..Default::default()
+    };
+    let wave: Vec<f32> = ChannelModel::new(channel_config, 48000, 17)
+        .transmit(&wave)
+        .iter()
+        .enumerate()
+        .map(|(t, x)| {
+            let t = t as f32 / 48000.0;
+            x + 0.3
+                + 0.3 * (2.0 * std::f32::consts::PI * 50.0 * t).sin()
+                + 0.3 * (2.0 * std::f32::consts::PI * 3500.0 * t).sin()
+        })
+        .collect();
+
+    let mut demodulator = Demodulation2::new_loopback(
+        config.clone(),
+        48000,
+        &loopback_output_file("front_end_output.txt"),
+        modulation::REDUNDANT_PERIODS,
+        false,
+    );
+    demodulator.set_constellation(Constellation::Qpsk);
+    let mut front_end_config = demodulator.front_end_config().clone();
+    assert!(front_end_config.high_pass.is_some() && front_end_config.band_pass.is_some());
+    front_end_config.notches.push((3500.0, 2.0));
+    demodulator.set_front_end(Some(front_end_config));
+
+    let mut decoded_data = vec![];
+    let mut debug_vec = vec![];
+    let test_data = loopback_chunks(&wave);
+    demodulator
+        .listening(
+            false,
+            phy_frame::FRAME_LENGTH_NO_ENCODING,
+            &mut decoded_data,
+            &mut debug_vec,
+            test_data,
+        )
+        .await;
+    assert_eq!(decoded_data, data);
+}
+
+#[test]
+fn test_agc() {
+    use crate::acoustic_modem::agc::{self, Agc};
+
+    // a quiet tone after a silence: the gain has fallen when the tone comes out
+    let mut agc = Agc::new(48000);
+    let mut input = vec![0.0; 24000];
+    input.extend(
+        (0..24000).map(|t| 0.01 * (2.0 * std::f32::consts::PI * 1200.0 * t as f32 / 48000.0).sin()),
+    );
+    let output: Vec<f32> = input.iter().map(|&x| agc.run(x)).collect();
+    let tone = &output[24000 + agc::LOOKAHEAD..];
+    let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+    let start = peak(&tone[..400]);
+    assert!((start - agc::TARGET_LEVEL).abs() < 0.1 * agc::TARGET_LEVEL, "{}", start);
+    let end = peak(&tone[tone.len() - 400..]);
+    assert!((end - agc::TARGET_LEVEL).abs() < 0.02 * agc::TARGET_LEVEL, "{}", end);
+
+    // the gain is held during a frame
+    agc.hold(true);
+    let gain = agc.gain();
+    for _ in 0..4800 {
+        agc.run(0.5);
+    }
+    assert_eq!(agc.gain(), gain);
+
+    // a silence can not be lifted, the full scale clips
+    let mut agc = Agc::new(48000);
+    for _ in 0..48000 {
+        agc.run(0.0);
+    }
+    agc.observe_input(1.0);
+    let level = agc.take_level();
+    assert!(level.is_too_quiet() && level.is_clipping());
+    assert!(!agc.take_level().is_clipping());
+}
+
+#[tokio::test]
+async fn test_loopback_frame_levels() {
+    // the AGC lifts the quiet signal and lowers the loud one, and reports the gain of each frame
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let mut gains = vec![];
+    for attenuation in [0.005, 1.0] {
+        let config = vec![CARRIER, 6000, 1];
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(Constellation::Qam16);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let channel_config = ChannelConfig {
+            attenuation,
+            leading_silence: (0, 4800),
+            trailing_silence: 1000,
+            snr_db: Some(30.0),
+            ..Default::default()
+        };
+        let wave = ChannelModel::new(channel_config, 48000, 21).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("frame_levels_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(Constellation::Qam16);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+        assert_eq!(decoded_data, data, "attenuation {}", attenuation);
+
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            assert!(!frame.level.is_too_quiet(), "{:?}", frame.level);
+            assert_eq!(frame.level.is_clipping(), attenuation == 1.0, "{:?}", frame.level);
+        }
+        gains.push(frames[0].level.gain);
+    }
+    // the gain follows the volume
+    let ratio = gains[0] / gains[1];
+    assert!(ratio > 100.0 && ratio < 400.0, "{:?}", gains);
+}
+
+#[tokio::test]
+async fn test_loopback_link_metrics() {
+    // the metrics of each frame follow the noise of the channel
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let mut snrs = vec![];
+    for snr_db in [30.0, 0.0] {
+        let config = vec![CARRIER, 6000, 1];
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(Constellation::Qpsk);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let channel_config = ChannelConfig {
+            attenuation: 0.5,
+            leading_silence: (0, 4800),
+            trailing_silence: 1000,
+            snr_db: Some(snr_db),
+            ..Default::default()
+        };
+        let wave = ChannelModel::new(channel_config, 48000, 22).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("link_metrics_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(Constellation::Qpsk);
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+        assert_eq!(decoded_data, data, "snr {}", snr_db);
+
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            assert!(frame.preamble_peak > 0.5 && frame.preamble_peak <= 1.0, "{:?}", frame);
+            assert!(frame.is_decoded(), "{:?}", frame);
+            let carrier = &frame.carriers[0];
+            // 2 bits per symbol
+            assert_eq!(carrier.symbols, phy_frame::FRAME_LENGTH_NO_ENCODING / 2, "{:?}", carrier);
+            assert!(carrier.margin.unwrap() > 0.3, "{:?}", carrier);
+            assert!(carrier.phase_error_var.unwrap() < 0.2, "{:?}", carrier);
+            // not encoded
+            assert_eq!(carrier.rs_corrections, None);
+            // soft bits of the length, the data and the CRC
+            assert_eq!(carrier.llrs.len(), phy_frame::FRAME_LENGTH_NO_ENCODING);
+            assert!(carrier.noise_var.unwrap() > 0.0);
+        }
+        snrs.push(frames[0].snr_db().unwrap());
+    }
+    assert!(snrs[0] > 15.0 && snrs[0] > snrs[1] + 3.0, "{:?}", snrs);
+}
+
+#[tokio::test]
+async fn test_loopback_equalizer() {
+    // the echoes smear the 8-PSK symbols into the next ones, the equalizer learns them from the
+    // training symbols and follows the clock skew
+    let data = utils::gen_random_data(phy_frame::MAX_FRAME_DATA_LENGTH * 2);
+    let channel_config = ChannelConfig {
+        attenuation: 0.3,
+        echo_taps: vec![(50, 0.6), (110, -0.4)],
+        clock_skew_ppm: 50.0,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db: Some(30.0),
+        ..Default::default()
+    };
+    let mut bit_errors = vec![];
+    for equalizer in [None, Some(EqualizerConfig::lms(64)), Some(EqualizerConfig::rls(32))] {
+        let config = vec![CARRIER, 6000, 1];
+        let training_len = equalizer.as_ref().map_or(0, |config| config.training_len);
+        let mut modulator = Modulator::new_loopback(config.clone(), 48000, false);
+        modulator.set_constellation(Constellation::Psk8);
+        modulator.set_training(training_len);
+        let wave = modulator
+            .bits_2_wave(read_data_2_compressed_u8(data.clone()), data.len() as isize)
+            .await;
+        let wave = ChannelModel::new(channel_config.clone(), 48000, 23).transmit(&wave);
+
+        let mut demodulator = Demodulation2::new_loopback(
+            config.clone(),
+            48000,
+            &loopback_output_file("equalizer_output.txt"),
+            modulation::REDUNDANT_PERIODS,
+            false,
+        );
+        demodulator.set_constellation(Constellation::Psk8);
+        demodulator.set_equalizer(equalizer.clone());
+        let mut decoded_data = vec![];
+        let mut debug_vec = vec![];
+        let test_data = loopback_chunks(&wave);
+        let frames = demodulator
+            .listening(
+                false,
+                phy_frame::FRAME_LENGTH_NO_ENCODING,
+                &mut decoded_data,
+                &mut debug_vec,
+                test_data,
+            )
+            .await;
+        assert_eq!(frames.len(), 2);
+        bit_errors.push(count_bit_errors(&data, &decoded_data));
+
+        if let Some(config) = &equalizer {
+            for frame in &frames {
+                let state = frame.carriers[0].equalizer.as_ref().unwrap();
+                assert_eq!(state.training_errors.len(), config.training_len);
+                // the error falls during the training and stays low after it
+                let quarter = config.training_len / 4;
+                let start_mse = state.training_errors[..quarter].iter().sum::<f32>() / quarter as f32;
+                let training_mse = state.training_mse().unwrap();
+                assert!(training_mse < 0.1 && training_mse < start_mse / 2.0, "{:?}", state);
+                assert!(state.tracking_mse.unwrap() < 0.1, "{:?}", state);
+            }
+        }
+    }
+    println!("bit errors: {:?}", bit_errors);
+    assert!(bit_errors[0] > 0, "{:?}", bit_errors);
+    assert_eq!(bit_errors[1..], [0, 0], "{:?}", bit_errors);
+}
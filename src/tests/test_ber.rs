@@ -0,0 +1,349 @@
+use crate::acoustic_modem::channel::ChannelConfig;
+use crate::acoustic_modem::constellation::Constellation;
+use crate::acoustic_modem::modulation::{self, Modulation};
+use crate::acoustic_modem::phy_frame;
+use crate::ber::{self, LinkConfig, Medium};
+use crate::pa1;
+
+fn temp_file(name: &str) -> String {
+    std::env::temp_dir()
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn channel_config(snr_db: Option<f32>) -> ChannelConfig {
+    ChannelConfig {
+        attenuation: 0.2,
+        leading_silence: (0, 4800),
+        trailing_silence: 1000,
+        snr_db,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_count_bit_errors() {
+    assert_eq!(
+        ber::count_bit_errors(&vec![0, 1, 1, 0], &vec![0, 1, 1, 0]),
+        0
+    );
+    assert_eq!(
+        ber::count_bit_errors(&vec![0, 1, 1, 0], &vec![1, 1, 1, 1]),
+        2
+    );
+    assert_eq!(ber::count_bit_errors(&vec![0, 1, 1, 0], &vec![0, 1]), 2);
+}
+
+#[test]
+fn test_compare_bit_files() {
+    let (file_1, file_2) = (temp_file("bits_1.txt"), temp_file("bits_2.txt"));
+    std::fs::write(&file_1, "0110\r\n1001\r\n").unwrap();
+    std::fs::write(&file_2, "01111001\n").unwrap();
+    assert_eq!(ber::compare_bit_files(&file_1, &file_2).unwrap(), 1);
+    std::fs::write(&file_2, "0110x001\n").unwrap();
+    assert!(ber::compare_bit_files(&file_1, &file_2).is_err());
+}
+
+#[tokio::test]
+async fn test_measure_single_carrier() {
+    let link_config = LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: 1,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Psk,
+        preamble: None,
+        preamble_threshold: None,
+    };
+    let result = ber::measure(
+        &link_config,
+        channel_config(Some(20.0)),
+        &Medium::Channel,
+        3,
+        0,
+    )
+    .await;
+    assert_eq!(result.trials, 3);
+    assert_eq!(result.bit_errors, 0);
+    assert_eq!(result.missed_preambles, 0);
+    assert_eq!(result.false_preambles, 0);
+    assert!(result.throughput() > 0.0);
+}
+
+#[tokio::test]
+async fn test_measure_qam16() {
+    let link_config = LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: 1,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Qam16,
+        modulation: Modulation::Psk,
+        preamble: None,
+        preamble_threshold: None,
+    };
+    let result = ber::measure(
+        &link_config,
+        channel_config(Some(30.0)),
+        &Medium::Channel,
+        3,
+        0,
+    )
+    .await;
+    assert_eq!(result.trials, 3);
+    assert_eq!(result.bit_errors, 0);
+    assert_eq!(result.missed_preambles, 0);
+}
+
+#[tokio::test]
+async fn test_measure_fsk() {
+    let link_config = LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: pa1::CARRIER_CNT,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Fsk,
+        preamble: None,
+        preamble_threshold: None,
+    };
+    let result = ber::measure(
+        &link_config,
+        channel_config(Some(5.0)),
+        &Medium::Channel,
+        3,
+        0,
+    )
+    .await;
+    assert_eq!(result.trials, 3);
+    assert_eq!(result.bits, 3 * phy_frame::MAX_FRAME_DATA_LENGTH);
+    assert_eq!(result.bit_errors, 0);
+}
+
+#[tokio::test]
+async fn test_measure_ofdm_wav_round_trip() {
+    let link_config = LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: 1200,
+        carrier_cnt: 2,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Ofdm,
+        preamble: None,
+        preamble_threshold: None,
+    };
+    let medium = Medium::WavRoundTrip(temp_file("ber_round_trip.wav"));
+    let result = ber::measure(&link_config, channel_config(None), &medium, 2, 0).await;
+    assert_eq!(result.trials, 2);
+    assert_eq!(result.frames, 4);
+    assert_eq!(result.frame_errors, 0);
+    assert_eq!(result.missed_preambles, 0);
+}
+
+#[tokio::test]
+async fn test_measure_carrier_settings() {
+    // a single carrier below and above the one of PA1, and OFDM subcarriers on other FFT sizes
+    for (carrier_low, carrier_interval, modulation) in [
+        (1200, pa1::CARRIER_INTERVAL, Modulation::Psk),
+        (4800, pa1::CARRIER_INTERVAL, Modulation::Psk),
+        (1200, 600, Modulation::Ofdm),
+        (pa1::CARRIER_LOW, 1200, Modulation::Ofdm),
+    ] {
+        let carrier_cnt = if modulation == Modulation::Ofdm {
+            pa1::CARRIER_CNT
+        } else {
+            1
+        };
+        let link_config = LinkConfig {
+            carrier_low,
+            carrier_interval,
+            carrier_cnt,
+            redundant_periods: modulation::REDUNDANT_PERIODS,
+            constellation: Constellation::Bpsk,
+            modulation,
+            preamble: None,
+            preamble_threshold: None,
+        };
+        let result = ber::measure(
+            &link_config,
+            channel_config(Some(20.0)),
+            &Medium::Channel,
+            2,
+            0,
+        )
+        .await;
+        assert_eq!(
+            result.frames,
+            2 * carrier_cnt as usize,
+            "{}",
+            link_config.label()
+        );
+        assert_eq!(result.frame_errors, 0, "{}", link_config.label());
+        assert_eq!(result.bit_errors, 0, "{}", link_config.label());
+    }
+}
+
+#[tokio::test]
+async fn test_sweep() {
+    let link_configs = vec![LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: 1,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Psk,
+        preamble: None,
+        preamble_threshold: None,
+    }];
+    let csv_file = temp_file("ber.csv");
+    let results = ber::sweep(
+        &link_configs,
+        &vec![-10.0, 20.0],
+        channel_config(None),
+        &Medium::Channel,
+        2,
+        &csv_file,
+        &temp_file("ber.svg"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(results.len(), 2);
+    let csv = std::fs::read_to_string(csv_file).unwrap();
+    assert_eq!(csv.lines().count(), 3);
+}
+
+#[tokio::test]
+async fn test_measure_preamble_families() {
+    use crate::acoustic_modem::preamble::{
+        BarkerPreamble, MSequencePreamble, Preamble, ZadoffChuPreamble,
+    };
+    use std::sync::Arc;
+
+    let preambles: Vec<(Arc<dyn Preamble>, Option<f32>)> = vec![
+        (
+            Arc::new(BarkerPreamble {
+                len: 13,
+                carrier: 3000.0,
+                chip_len: 24,
+            }),
+            Some(0.5),
+        ),
+        (
+            Arc::new(MSequencePreamble {
+                degree: 6,
+                carrier: 4000.0,
+                chip_len: 12,
+            }),
+            None,
+        ),
+        (
+            Arc::new(ZadoffChuPreamble {
+                root: 25,
+                len: 63,
+                carrier: 4000.0,
+                chip_len: 12,
+            }),
+            None,
+        ),
+    ];
+    for (preamble, preamble_threshold) in preambles {
+        let link_config = LinkConfig {
+            carrier_low: pa1::CARRIER_LOW,
+            carrier_interval: pa1::CARRIER_INTERVAL,
+            carrier_cnt: 1,
+            redundant_periods: modulation::REDUNDANT_PERIODS,
+            constellation: Constellation::Bpsk,
+            modulation: Modulation::Psk,
+            preamble: Some(preamble),
+            preamble_threshold,
+        };
+        let result = ber::measure(
+            &link_config,
+            channel_config(Some(10.0)),
+            &Medium::Channel,
+            3,
+            0,
+        )
+        .await;
+        assert_eq!(result.bit_errors, 0, "{}", link_config.label());
+        assert_eq!(result.missed_preambles, 0, "{}", link_config.label());
+        assert_eq!(result.false_preambles, 0, "{}", link_config.label());
+    }
+}
+
+// the default threshold against 0.5: the chirp preamble of CSS is still found at -10dB, the short
+// Barker 13 preamble needs 0.5 against the false preambles
+#[tokio::test]
+async fn test_measure_preamble_threshold() {
+    use crate::acoustic_modem::css;
+    use crate::acoustic_modem::preamble::BarkerPreamble;
+    use std::sync::Arc;
+
+    let css_link = |preamble_threshold| LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: 1,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Css(css::CSS_DEFAULT_SPREADING_FACTOR),
+        preamble: None,
+        preamble_threshold,
+    };
+    let default_result = ber::measure(
+        &css_link(None),
+        channel_config(Some(-10.0)),
+        &Medium::Channel,
+        10,
+        3,
+    )
+    .await;
+    let strict_result = ber::measure(
+        &css_link(Some(0.5)),
+        channel_config(Some(-10.0)),
+        &Medium::Channel,
+        10,
+        3,
+    )
+    .await;
+    assert!(default_result.missed_preambles <= 2);
+    assert_eq!(default_result.false_preambles, 0);
+    assert!(strict_result.missed_preambles >= 5);
+
+    let barker_link = |preamble_threshold| LinkConfig {
+        carrier_low: pa1::CARRIER_LOW,
+        carrier_interval: pa1::CARRIER_INTERVAL,
+        carrier_cnt: 1,
+        redundant_periods: modulation::REDUNDANT_PERIODS,
+        constellation: Constellation::Bpsk,
+        modulation: Modulation::Psk,
+        preamble: Some(Arc::new(BarkerPreamble {
+            len: 13,
+            carrier: 3000.0,
+            chip_len: 24,
+        })),
+        preamble_threshold,
+    };
+    let default_result = ber::measure(
+        &barker_link(None),
+        channel_config(Some(5.0)),
+        &Medium::Channel,
+        10,
+        3,
+    )
+    .await;
+    let strict_result = ber::measure(
+        &barker_link(Some(0.5)),
+        channel_config(Some(5.0)),
+        &Medium::Channel,
+        10,
+        3,
+    )
+    .await;
+    assert!(default_result.false_preambles > 0);
+    assert_eq!(strict_result.missed_preambles, 0);
+    assert_eq!(strict_result.false_preambles, 0);
+}